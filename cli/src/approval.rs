@@ -0,0 +1,454 @@
+// Design: what a receiver accepts. A rule's `name`/`mime` clauses must hold for every file, and MIME
+// types are what the sender announces, not sniffed from the data.
+
+use anyhow::{anyhow, Result};
+use std::str::FromStr;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::sync::{mpsc, Mutex};
+
+use crate::log_line;
+use crate::progress::{format_bytes, plural};
+
+// Longer offers are summarized after this many files.
+const PREVIEW_FILES: usize = 20;
+
+#[derive(Clone)]
+pub enum Approval {
+  Auto,
+  Prompt,
+  Rule(Rule),
+}
+
+pub struct OfferedFile {
+  pub name: String,
+  // None for a stream of unknown length.
+  pub size: Option<u64>,
+  // Absent from the manifests of older senders and the browser.
+  pub mime: Option<String>,
+}
+
+pub enum Decision {
+  Accept,
+  Decline(String),
+}
+
+#[derive(Clone, Debug)]
+pub struct Rule {
+  source: String,
+  clauses: Vec<Clause>,
+}
+
+#[derive(Clone, Debug)]
+enum Clause {
+  Size(Compare, u64),
+  Files(Compare, u64),
+  Name { equal: bool, pattern: String },
+  Mime { equal: bool, pattern: String },
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Compare {
+  Less,
+  LessOrEqual,
+  Greater,
+  GreaterOrEqual,
+  Equal,
+  NotEqual,
+}
+
+// Answers typed on stdin for `--confirm` and `--on-conflict ask`. One task, started by the first
+// question, reads stdin for the rest of the session and hands the lines over, so a question waits on
+// a channel rather than on the terminal, and a line typed ahead is not lost between questions.
+#[derive(Default)]
+pub struct Prompter {
+  lines: Mutex<Option<mpsc::UnboundedReceiver<String>>>,
+}
+
+// `--max-size`, `--allow-mime` and `--deny-ext`.
+pub struct Limits {
+  pub max_size: Option<u64>,
+  pub allow_mime: Vec<String>,
+  // Lower case, without the leading dot.
+  pub deny_ext: Vec<String>,
+}
+
+impl Approval {
+  pub fn is_auto(&self) -> bool {
+    matches!(self, Approval::Auto)
+  }
+}
+
+// Logs the offer and decides on it; prompting fails only when stdin goes away.
+pub async fn decide(approval: &Approval, prompter: &Prompter, files: &[OfferedFile]) -> Result<Decision> {
+  preview(files);
+  match approval {
+    Approval::Auto => Ok(Decision::Accept),
+    Approval::Rule(rule) => Ok(rule.check(files)),
+    Approval::Prompt => prompt(prompter).await,
+  }
+}
+
+fn preview(files: &[OfferedFile]) {
+  let total = match files.iter().map(|file| file.size).sum::<Option<u64>>() {
+    Some(total) => format_bytes(total),
+    None => "size unknown".to_string(),
+  };
+  log_line("[recv] offer", &format!("{} ({total})", plural(files.len(), "file")));
+  for file in files.iter().take(PREVIEW_FILES) {
+    let size = file.size.map_or("size unknown".to_string(), format_bytes);
+    match file.mime.as_deref() {
+      Some(mime) => log_line("[recv] offer", &format!("  {} ({size}, {mime})", file.name)),
+      None => log_line("[recv] offer", &format!("  {} ({size})", file.name)),
+    }
+  }
+  if files.len() > PREVIEW_FILES {
+    log_line("[recv] offer", &format!("  ... and {} more", files.len() - PREVIEW_FILES));
+  }
+}
+
+async fn prompt(prompter: &Prompter) -> Result<Decision> {
+  let accepted = prompter
+    .ask("Accept this transfer? [y/N]", |answer| match answer.to_ascii_lowercase().as_str() {
+      "y" | "yes" => Some(true),
+      "" | "n" | "no" => Some(false),
+      _ => None,
+    })
+    .await
+    .ok_or_else(|| anyhow!("stdin closed before the transfer was accepted"))?;
+  Ok(if accepted {
+    Decision::Accept
+  } else {
+    Decision::Decline("answered no at the prompt".to_string())
+  })
+}
+
+impl Prompter {
+  // Asks `question` until `parse` takes the trimmed answer; None once stdin is closed.
+  pub async fn ask<T>(&self, question: &str, mut parse: impl FnMut(&str) -> Option<T>) -> Option<T> {
+    let mut lines = self.lines.lock().await;
+    let lines = lines.get_or_insert_with(read_stdin);
+    loop {
+      eprint!("{question} ");
+      let _ = std::io::Write::flush(&mut std::io::stderr());
+      if let Some(answer) = parse(lines.recv().await?.trim()) {
+        return Some(answer);
+      }
+    }
+  }
+}
+
+fn read_stdin() -> mpsc::UnboundedReceiver<String> {
+  let (tx, rx) = mpsc::unbounded_channel();
+  tokio::spawn(async move {
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+      if tx.send(line).is_err() {
+        break;
+      }
+    }
+  });
+  rx
+}
+
+impl Limits {
+  // A file without a MIME type (manifests from older senders) is checked again once its meta arrives.
+  pub fn check(&self, files: &[OfferedFile]) -> std::result::Result<(), String> {
+    for file in files {
+      if let (Some(max_size), Some(size)) = (self.max_size, file.size) {
+        if size > max_size {
+          return Err(format!(
+            "{} is {}, over --max-size {}",
+            file.name,
+            format_bytes(size),
+            format_bytes(max_size)
+          ));
+        }
+      }
+      let lower = file.name.to_lowercase();
+      if let Some(ext) = self.deny_ext.iter().find(|ext| lower.ends_with(&format!(".{ext}"))) {
+        return Err(format!("{}: .{ext} files are not accepted", file.name));
+      }
+      if let Some(mime) = file.mime.as_deref().filter(|_| !self.allow_mime.is_empty()) {
+        if !self.allow_mime.iter().any(|pattern| wildcard(pattern, mime)) {
+          return Err(format!("{}: {mime} is not an accepted type", file.name));
+        }
+      }
+    }
+    Ok(())
+  }
+
+  // For a stream whose size was not announced.
+  pub fn check_received(&self, received: u64) -> std::result::Result<(), String> {
+    match self.max_size {
+      Some(max_size) if received > max_size => Err(format!("stream grew past --max-size {}", format_bytes(max_size))),
+      _ => Ok(()),
+    }
+  }
+}
+
+impl Rule {
+  fn check(&self, files: &[OfferedFile]) -> Decision {
+    for clause in &self.clauses {
+      if let Err(reason) = clause.check(files) {
+        return Decision::Decline(format!("--accept-if '{}': {reason}", self.source));
+      }
+    }
+    Decision::Accept
+  }
+}
+
+impl Clause {
+  fn check(&self, files: &[OfferedFile]) -> std::result::Result<(), String> {
+    match self {
+      Clause::Size(compare, limit) => {
+        let total = files
+          .iter()
+          .map(|file| file.size)
+          .sum::<Option<u64>>()
+          .ok_or_else(|| "the size is not known in advance".to_string())?;
+        if compare.holds(total, *limit) {
+          Ok(())
+        } else {
+          Err(format!("size is {total} bytes"))
+        }
+      }
+      Clause::Files(compare, limit) => {
+        let count = files.len() as u64;
+        if compare.holds(count, *limit) {
+          Ok(())
+        } else {
+          Err(format!("{count} files offered"))
+        }
+      }
+      Clause::Name { equal, pattern } => match files.iter().find(|file| wildcard(pattern, &file.name) != *equal) {
+        Some(file) => Err(format!("name {} does not match", file.name)),
+        None => Ok(()),
+      },
+      Clause::Mime { equal, pattern } => {
+        for file in files {
+          let mime = file.mime.as_deref().ok_or_else(|| format!("no MIME type for {}", file.name))?;
+          if wildcard(pattern, mime) != *equal {
+            return Err(format!("{} is {mime}", file.name));
+          }
+        }
+        Ok(())
+      }
+    }
+  }
+}
+
+impl Compare {
+  fn holds(self, value: u64, limit: u64) -> bool {
+    match self {
+      Compare::Less => value < limit,
+      Compare::LessOrEqual => value <= limit,
+      Compare::Greater => value > limit,
+      Compare::GreaterOrEqual => value >= limit,
+      Compare::Equal => value == limit,
+      Compare::NotEqual => value != limit,
+    }
+  }
+}
+
+impl FromStr for Rule {
+  type Err = String;
+
+  fn from_str(source: &str) -> std::result::Result<Self, Self::Err> {
+    let clauses = source.split("&&").map(parse_clause).collect::<std::result::Result<Vec<_>, _>>()?;
+    Ok(Rule {
+      source: source.trim().to_string(),
+      clauses,
+    })
+  }
+}
+
+fn parse_clause(clause: &str) -> std::result::Result<Clause, String> {
+  let clause = clause.trim();
+  let start = clause
+    .find(['<', '>', '=', '!'])
+    .ok_or_else(|| format!("`{clause}` has no comparison (use <, <=, >, >=, = or !=)"))?;
+  let (field, rest) = clause.split_at(start);
+  let (compare, value) = [
+    ("<=", Compare::LessOrEqual),
+    (">=", Compare::GreaterOrEqual),
+    ("!=", Compare::NotEqual),
+    ("==", Compare::Equal),
+    ("<", Compare::Less),
+    (">", Compare::Greater),
+    ("=", Compare::Equal),
+  ]
+  .into_iter()
+  .find_map(|(op, compare)| rest.strip_prefix(op).map(|value| (compare, value.trim())))
+  .ok_or_else(|| format!("`{clause}` has no comparison"))?;
+  let equal = match compare {
+    Compare::Equal => Some(true),
+    Compare::NotEqual => Some(false),
+    _ => None,
+  };
+  match field.trim().to_ascii_lowercase().as_str() {
+    "size" => Ok(Clause::Size(compare, parse_size(value)?)),
+    "files" => Ok(Clause::Files(
+      compare,
+      value.parse().map_err(|_| format!("`{value}` is not a number of files"))?,
+    )),
+    "name" => Ok(Clause::Name {
+      equal: equal.ok_or("name only compares with = or !=")?,
+      pattern: value.to_string(),
+    }),
+    "mime" => Ok(Clause::Mime {
+      equal: equal.ok_or("mime only compares with = or !=")?,
+      pattern: value.to_string(),
+    }),
+    other => Err(format!("unknown field `{other}` (use size, files, name or mime)")),
+  }
+}
+
+// `1500`, `500K`, `1.5G`, `2GiB`, ...
+pub fn parse_size(value: &str) -> std::result::Result<u64, String> {
+  let digits = value.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(value.len());
+  let (number, unit) = value.split_at(digits);
+  let number: f64 = number.parse().map_err(|_| format!("`{value}` is not a size"))?;
+  let shift = match unit.trim().to_ascii_lowercase().trim_end_matches("ib").trim_end_matches('b') {
+    "" => 0,
+    "k" => 10,
+    "m" => 20,
+    "g" => 30,
+    "t" => 40,
+    _ => return Err(format!("`{value}` has an unknown unit (use K, M, G or T)")),
+  };
+  let bytes = number * (1u64 << shift) as f64;
+  // Casting would quietly cap anything larger at u64::MAX.
+  if bytes >= u64::MAX as f64 {
+    return Err(format!("`{value}` is too large"));
+  }
+  Ok(bytes as u64)
+}
+
+// `*` matches any run of characters; everything else matches itself.
+fn wildcard(pattern: &str, text: &str) -> bool {
+  let pattern: Vec<char> = pattern.chars().collect();
+  let text: Vec<char> = text.chars().collect();
+  let (mut p, mut t) = (0, 0);
+  let mut backtrack = None;
+  while t < text.len() {
+    if p < pattern.len() && pattern[p] == '*' {
+      backtrack = Some((p, t));
+      p += 1;
+    } else if p < pattern.len() && pattern[p] == text[t] {
+      p += 1;
+      t += 1;
+    } else if let Some((star, from)) = backtrack {
+      p = star + 1;
+      t = from + 1;
+      backtrack = Some((star, from + 1));
+    } else {
+      return false;
+    }
+  }
+  pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn offered(name: &str, size: u64, mime: &str) -> OfferedFile {
+    OfferedFile {
+      name: name.to_string(),
+      size: Some(size),
+      mime: Some(mime.to_string()),
+    }
+  }
+
+  fn accepts(rule: &str, files: &[OfferedFile]) -> bool {
+    matches!(rule.parse::<Rule>().unwrap().check(files), Decision::Accept)
+  }
+
+  #[test]
+  fn parse_size_reads_binary_units() {
+    assert_eq!(parse_size("1500"), Ok(1500));
+    assert_eq!(parse_size("500K"), Ok(500 * 1024));
+    assert_eq!(parse_size("1.5G"), Ok(3 * 512 * 1024 * 1024));
+    assert_eq!(parse_size("2GiB"), Ok(2 << 30));
+    assert_eq!(parse_size("1 tb"), Ok(1 << 40));
+    for value in ["", "G", "12X", "1.2.3M"] {
+      assert!(parse_size(value).is_err(), "{value}");
+    }
+  }
+
+  #[test]
+  fn parse_size_rejects_sizes_past_u64() {
+    assert_eq!(parse_size("16777215T"), Ok(16777215 << 40));
+    for value in ["16777216T", "18446744073709551616", "99999999999999999999999G"] {
+      assert!(parse_size(value).unwrap_err().contains("too large"), "{value}");
+    }
+  }
+
+  #[test]
+  fn limits_check_every_file() {
+    let limits = Limits {
+      max_size: Some(1000),
+      allow_mime: vec!["image/*".to_string()],
+      deny_ext: vec!["exe".to_string()],
+    };
+    assert!(limits.check(&[offered("a.jpg", 1000, "image/jpeg"), offered("b.png", 10, "image/png")]).is_ok());
+    assert!(limits.check(&[offered("a.jpg", 10, "image/jpeg"), offered("b.png", 1001, "image/png")]).unwrap_err().contains("--max-size"));
+    assert!(limits.check(&[offered("SETUP.EXE", 10, "image/png")]).unwrap_err().contains(".exe"));
+    assert!(limits.check(&[offered("a.pdf", 10, "application/pdf")]).unwrap_err().contains("not an accepted type"));
+    // Checked again once the meta says what they are.
+    let unknown = OfferedFile {
+      name: "stdin".to_string(),
+      size: None,
+      mime: None,
+    };
+    assert!(limits.check(&[unknown]).is_ok());
+    assert!(limits.check_received(1000).is_ok());
+    assert!(limits.check_received(1001).is_err());
+  }
+
+  #[test]
+  fn parse_clause_reads_every_comparison() {
+    assert!(matches!(parse_clause("size<1G"), Ok(Clause::Size(Compare::Less, 1073741824))));
+    assert!(matches!(parse_clause(" size <= 10 "), Ok(Clause::Size(Compare::LessOrEqual, 10))));
+    assert!(matches!(parse_clause("files>=3"), Ok(Clause::Files(Compare::GreaterOrEqual, 3))));
+    assert!(matches!(parse_clause("files==3"), Ok(Clause::Files(Compare::Equal, 3))));
+    assert!(matches!(parse_clause("FILES!=3"), Ok(Clause::Files(Compare::NotEqual, 3))));
+    assert!(matches!(parse_clause("name=*.jpg"), Ok(Clause::Name { equal: true, .. })));
+    assert!(matches!(parse_clause("mime != video/*"), Ok(Clause::Mime { equal: false, .. })));
+  }
+
+  #[test]
+  fn parse_clause_rejects_bad_clauses() {
+    for clause in ["size", "size~1G", "name<a", "mime>=x", "files<many", "color=red", "size<lots"] {
+      assert!(parse_clause(clause).is_err(), "{clause}");
+    }
+  }
+
+  #[test]
+  fn wildcard_matches_runs_of_characters() {
+    assert!(wildcard("*.jpg", "photo.jpg"));
+    assert!(wildcard("image/*", "image/png"));
+    assert!(wildcard("*", ""));
+    assert!(wildcard("a*b*c", "aXbYbZc"));
+    assert!(wildcard("report", "report"));
+    assert!(!wildcard("*.jpg", "photo.jpeg"));
+    assert!(!wildcard("image/*", "video/mp4"));
+    assert!(!wildcard("a*b", "ac"));
+    assert!(!wildcard("", "x"));
+  }
+
+  #[test]
+  fn rule_holds_for_every_file() {
+    let files = [offered("a.jpg", 1024, "image/jpeg"), offered("b.png", 2048, "image/png")];
+    assert!(accepts("size<1M && files=2 && mime=image/*", &files));
+    assert!(!accepts("size<3K", &files));
+    assert!(!accepts("name=*.jpg", &files));
+    assert!(accepts("name!=*.exe", &files));
+    let stream = [OfferedFile {
+      name: "stdin".to_string(),
+      size: None,
+      mime: None,
+    }];
+    assert!(!accepts("size<1G", &stream));
+  }
+}
@@ -0,0 +1,137 @@
+// Design: short transfer codes (`send --code`); the words are the PAKE password, the number picks the room.
+
+use anyhow::{anyhow, Result};
+use getrandom::getrandom;
+
+// Rooms for codes live under their own prefix so they never collide with server-issued room IDs.
+const ROOM_PREFIX: &str = "code-";
+const NAMEPLATE_MAX: u16 = 999;
+// 16 bits: enough for one online guess, which is all an attacker gets before the code burns.
+const WORD_COUNT: usize = 2;
+
+// 256 words, so each word carries one byte; easy to spell out loud and free of near-homophones.
+const WORDS: [&str; 256] = [
+  "acid", "acorn", "actor", "adobe", "agent", "alarm", "album", "alpha", "amber", "anchor",
+  "angle", "apple", "april", "arena", "armor", "arrow", "atlas", "attic", "autumn", "bacon",
+  "badge", "bagel", "baker", "bamboo", "banana", "banjo", "barn", "basket", "beacon", "beetle",
+  "bench", "berry", "bicycle", "biscuit", "blanket", "blossom", "boat", "bonfire", "breeze",
+  "brick", "bridge", "bubble", "bucket", "buffalo", "button", "cabin", "cactus", "camera",
+  "candle", "canoe", "canyon", "captain", "carbon", "carpet", "carrot", "castle", "cedar", "cello",
+  "chalk", "cherry", "chess", "circus", "citrus", "cloud", "clover", "cobalt", "coconut", "comet",
+  "compass", "copper", "coral", "cotton", "cougar", "crayon", "cricket", "crossword", "crystal",
+  "curtain", "cushion", "daisy", "dancer", "delta", "desert", "diamond", "dinner", "dolphin",
+  "domino", "donkey", "dragon", "drum", "eagle", "earth", "echo", "eclipse", "elbow", "ember",
+  "emerald", "engine", "falcon", "feather", "fiddle", "fig", "finch", "flute", "forest", "fossil",
+  "fountain", "fox", "galaxy", "garden", "garlic", "gecko", "ginger", "glacier", "globe",
+  "granite", "grape", "gravel", "guitar", "hammer", "harbor", "harvest", "hazel", "helmet",
+  "heron", "honey", "horizon", "hornet", "husky", "igloo", "indigo", "iris", "island", "ivory",
+  "jacket", "jaguar", "jelly", "jigsaw", "jungle", "kayak", "kettle", "kiwi", "koala", "ladder",
+  "lagoon", "lantern", "laser", "lemon", "leopard", "lilac", "lizard", "lobster", "lotus",
+  "magnet", "mango", "maple", "marble", "meadow", "melon", "meteor", "mint", "mirror", "mitten",
+  "monkey", "mountain", "muffin", "nectar", "needle", "nickel", "noodle", "oasis", "ocean",
+  "octopus", "olive", "onion", "orange", "orbit", "orchid", "otter", "owl", "paddle", "panda",
+  "panther", "paper", "parrot", "peach", "peanut", "pebble", "pelican", "pepper", "piano", "pilot",
+  "pine", "pirate", "planet", "plum", "pocket", "polar", "pony", "poppy", "potato", "puzzle",
+  "pyramid", "quartz", "quill", "rabbit", "radar", "radish", "raven", "ribbon", "river", "robin",
+  "rocket", "saddle", "salmon", "scarf", "shadow", "shell", "silver", "sketch", "sparrow",
+  "spider", "stable", "stamp", "storm", "sugar", "summit", "sunset", "swan", "tango", "teapot",
+  "thunder", "tiger", "timber", "tomato", "topaz", "tornado", "trumpet", "tulip", "tunnel",
+  "turtle", "valley", "velvet", "violin", "waffle", "wagon", "walnut", "walrus", "whale", "willow",
+  "window", "winter", "wizard", "yogurt", "zebra", "zipper",
+];
+
+pub struct TransferCode {
+  pub nameplate: u16,
+  pub words: Vec<&'static str>,
+}
+
+impl TransferCode {
+  pub fn generate() -> Result<Self> {
+    let mut bytes = [0u8; 2 + WORD_COUNT];
+    getrandom(&mut bytes).map_err(|err| anyhow!(err))?;
+    let nameplate = u16::from_be_bytes([bytes[0], bytes[1]]) % NAMEPLATE_MAX + 1;
+    let words = bytes[2..].iter().map(|byte| WORDS[*byte as usize]).collect();
+    Ok(TransferCode { nameplate, words })
+  }
+
+  // Accepts `7-crossword-banana`; returns None for anything that does not look like a code
+  // (room IDs and URLs), and an error for a code with a word that is not in the list.
+  pub fn parse(value: &str) -> Option<Result<Self>> {
+    let value = value.trim().to_ascii_lowercase();
+    let mut parts = value.split('-');
+    let nameplate = parts.next()?.parse::<u16>().ok().filter(|n| *n > 0)?;
+    let words: Vec<&str> = parts.collect();
+    if words.len() != WORD_COUNT || words.iter().any(|word| word.is_empty() || !word.chars().all(|c| c.is_ascii_lowercase())) {
+      return None;
+    }
+    let words = words
+      .into_iter()
+      .map(|word| {
+        WORDS
+          .iter()
+          .find(|known| **known == word)
+          .copied()
+          .ok_or_else(|| anyhow!("Unknown word in code: {word}"))
+      })
+      .collect::<Result<Vec<_>>>();
+    Some(words.map(|words| TransferCode { nameplate, words }))
+  }
+
+  pub fn room_id(&self) -> String {
+    format!("{ROOM_PREFIX}{}", self.nameplate)
+  }
+
+  pub fn password(&self) -> String {
+    self.to_string()
+  }
+}
+
+impl std::fmt::Display for TransferCode {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "{}-{}", self.nameplate, self.words.join("-"))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn parse_reads_a_code() {
+    let code = TransferCode::parse(" 7-Crossword-BANANA ").unwrap().unwrap();
+    assert_eq!(code.nameplate, 7);
+    assert_eq!(code.words, vec!["crossword", "banana"]);
+    assert_eq!(code.room_id(), "code-7");
+    assert_eq!(code.to_string(), "7-crossword-banana");
+  }
+
+  #[test]
+  fn parse_leaves_room_ids_and_urls_alone() {
+    for value in [
+      "ABCDEFGH23",
+      "https://getpairlane.com/r/ABCDEFGH23",
+      "0-crossword-banana",
+      "x-crossword-banana",
+      "7-crossword",
+      "7-crossword-banana-apple",
+      "7--banana",
+      "7-cross word-banana",
+    ] {
+      assert!(TransferCode::parse(value).is_none(), "{value}");
+    }
+  }
+
+  #[test]
+  fn parse_rejects_unknown_words() {
+    assert!(TransferCode::parse("7-crossword-zzzz").unwrap().is_err());
+  }
+
+  #[test]
+  fn generate_round_trips_through_parse() {
+    let code = TransferCode::generate().unwrap();
+    assert!((1..=NAMEPLATE_MAX).contains(&code.nameplate));
+    let parsed = TransferCode::parse(&code.to_string()).unwrap().unwrap();
+    assert_eq!(parsed.nameplate, code.nameplate);
+    assert_eq!(parsed.words, code.words);
+  }
+}
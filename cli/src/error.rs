@@ -0,0 +1,122 @@
+// Design: failure classes shared by the exit status and the `--json` error codes. Code that knows
+// why a transfer failed raises a `TransferError` of the matching kind; anything else is classified
+// from the io, websocket or timer error at the bottom of the chain, and falls back to "internal".
+// Exit codes are part of the CLI's interface (see README.md), so existing numbers never change.
+
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+  // The signaling server could not be reached, refused the room or dropped the connection.
+  Signaling,
+  // No peer-to-peer path could be established.
+  Ice,
+  // Wrong room key or code, or the other side rejected ours.
+  Auth,
+  // A file did not match its announced size or hash, or a frame failed to decrypt.
+  Integrity,
+  Io,
+  // The other side closed the channel or gave up mid-transfer.
+  PeerAbort,
+  Timeout,
+  // The receiver's --exec or --on-complete command failed.
+  Hook,
+  // The receiver turned the transfer down (--confirm or --accept-if).
+  Declined,
+}
+
+impl ErrorKind {
+  pub fn code(self) -> &'static str {
+    match self {
+      ErrorKind::Signaling => "signaling",
+      ErrorKind::Ice => "ice",
+      ErrorKind::Auth => "auth",
+      ErrorKind::Integrity => "integrity",
+      ErrorKind::Io => "io",
+      ErrorKind::PeerAbort => "peer-abort",
+      ErrorKind::Timeout => "timeout",
+      ErrorKind::Hook => "hook",
+      ErrorKind::Declined => "declined",
+    }
+  }
+
+  // 1 is any other failure and 2 is a usage error (reported by clap).
+  pub fn exit_code(self) -> u8 {
+    match self {
+      ErrorKind::Signaling => 3,
+      ErrorKind::Ice => 4,
+      ErrorKind::Auth => 5,
+      ErrorKind::Integrity => 6,
+      ErrorKind::Io => 7,
+      ErrorKind::PeerAbort => 8,
+      ErrorKind::Timeout => 9,
+      ErrorKind::Hook => 10,
+      ErrorKind::Declined => 11,
+    }
+  }
+
+  pub fn error(self, message: impl Into<String>) -> anyhow::Error {
+    anyhow::Error::new(TransferError {
+      kind: self,
+      message: message.into(),
+    })
+  }
+
+  // Tags an existing error unless something further down already said what went wrong.
+  pub fn wrap(self, err: anyhow::Error) -> anyhow::Error {
+    if err.chain().any(|cause| cause.is::<TransferError>()) {
+      return err;
+    }
+    self.error(format!("{err:#}"))
+  }
+}
+
+#[derive(Debug)]
+pub struct TransferError {
+  kind: ErrorKind,
+  message: String,
+}
+
+impl fmt::Display for TransferError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(&self.message)
+  }
+}
+
+impl std::error::Error for TransferError {}
+
+pub trait ResultExt<T> {
+  fn kind(self, kind: ErrorKind) -> anyhow::Result<T>;
+}
+
+impl<T, E: Into<anyhow::Error>> ResultExt<T> for Result<T, E> {
+  fn kind(self, kind: ErrorKind) -> anyhow::Result<T> {
+    self.map_err(|err| kind.wrap(err.into()))
+  }
+}
+
+pub fn kind_of(err: &anyhow::Error) -> Option<ErrorKind> {
+  if let Some(err) = err.chain().find_map(|cause| cause.downcast_ref::<TransferError>()) {
+    return Some(err.kind);
+  }
+  for cause in err.chain() {
+    if cause.is::<tokio_tungstenite::tungstenite::Error>() || cause.is::<reqwest::Error>() {
+      return Some(ErrorKind::Signaling);
+    }
+    if cause.is::<tokio::time::error::Elapsed>() {
+      return Some(ErrorKind::Timeout);
+    }
+    if cause.is::<std::io::Error>() {
+      return Some(ErrorKind::Io);
+    }
+  }
+  None
+}
+
+pub fn code(err: &anyhow::Error) -> &'static str {
+  kind_of(err).map_or("internal", ErrorKind::code)
+}
+
+pub fn exit_code(err: &anyhow::Error) -> u8 {
+  kind_of(err).map_or(1, ErrorKind::exit_code)
+}
@@ -0,0 +1,121 @@
+// Design: `--json` output for scripts. Every line on stdout is one event object carrying the schema
+// version `v`, a UTC timestamp and the event name; human-readable logs move to stderr. Adding an
+// event or a field is compatible; renaming or removing one needs a new SCHEMA_VERSION.
+
+use serde::Serialize;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+pub const SCHEMA_VERSION: u32 = 1;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+pub fn enable() {
+  ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+  ENABLED.load(Ordering::Relaxed)
+}
+
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum Event<'a> {
+  // `url` is absent when sending with `--code`, which prints `code` instead.
+  RoomCreated {
+    id: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    url: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code: Option<&'a str>,
+  },
+  // `room info`: the room's settings.
+  RoomInfo {
+    id: &'a str,
+    #[serde(rename = "maxConcurrent")]
+    max_concurrent: u32,
+  },
+  Role { role: &'a str, cid: &'a str },
+  // The signaling websocket dropped; the next attempt starts `delay` seconds from now. Peer
+  // connections carry on meanwhile.
+  Reconnecting { attempt: u32, delay: u64 },
+  Reconnected,
+  // `position` is null while the room does not report one.
+  Queue { position: Option<u32> },
+  PeerJoined {
+    #[serde(rename = "peerId")]
+    peer_id: &'a str,
+  },
+  PeerLeft {
+    #[serde(rename = "peerId")]
+    peer_id: &'a str,
+  },
+  // `state` is the RTCPeerConnection state in lower case, e.g. "connected" or "failed".
+  Connection {
+    #[serde(rename = "peerId", skip_serializing_if = "Option::is_none")]
+    peer_id: Option<&'a str>,
+    state: String,
+  },
+  // `size` is null for a stream of unknown length (`send -`).
+  Meta {
+    name: &'a str,
+    size: Option<u64>,
+    mime: &'a str,
+    offset: u64,
+  },
+  Progress {
+    #[serde(rename = "peerId", skip_serializing_if = "Option::is_none")]
+    peer_id: Option<&'a str>,
+    name: &'a str,
+    bytes: u64,
+    // Null for a stream of unknown length.
+    total: Option<u64>,
+    // Smoothed bytes per second; null until the first sample.
+    rate: Option<u64>,
+  },
+  // One per file: written and verified on the receiver, acknowledged by a receiver on the sender.
+  Completed {
+    #[serde(rename = "peerId", skip_serializing_if = "Option::is_none")]
+    peer_id: Option<&'a str>,
+    path: &'a str,
+    size: u64,
+    sha256: &'a str,
+  },
+  // Receiver only: the file already existed and `--on-conflict` kept it.
+  Skipped { path: &'a str },
+  // `--manual-signaling`: the line to hand to the other side; `kind` is "offer" or "answer".
+  Signal { kind: &'a str, signal: &'a str },
+  // The whole transfer finished; the process exits next unless `--stay-open` is set.
+  Done,
+  Error {
+    code: &'a str,
+    message: String,
+    #[serde(rename = "peerId", skip_serializing_if = "Option::is_none")]
+    peer_id: Option<&'a str>,
+  },
+}
+
+#[derive(Serialize)]
+struct Envelope<'a> {
+  v: u32,
+  ts: String,
+  #[serde(flatten)]
+  event: &'a Event<'a>,
+}
+
+pub fn emit(event: Event) {
+  if !enabled() {
+    return;
+  }
+  let envelope = Envelope {
+    v: SCHEMA_VERSION,
+    ts: chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+    event: &event,
+  };
+  let Ok(line) = serde_json::to_string(&envelope) else {
+    return;
+  };
+  let mut out = std::io::stdout().lock();
+  let _ = writeln!(out, "{line}");
+  let _ = out.flush();
+}
@@ -0,0 +1,185 @@
+// Design: `receive --exec` and `--on-complete`. Values are substituted shell-quoted, and a value that is
+// not known yet (no path with --exec, no size or hash for a stream) becomes empty and leaves its
+// PAIRLANE_* variable unset.
+
+use anyhow::{Context, Result};
+use std::path::Path;
+use std::process::{ExitStatus, Stdio};
+use tokio::process::{Child, Command};
+
+use crate::error::ErrorKind;
+
+pub struct FileDetails<'a> {
+  pub path: Option<&'a Path>,
+  pub name: &'a str,
+  pub size: Option<u64>,
+  pub mime: &'a str,
+  pub sha256: Option<&'a str>,
+}
+
+impl FileDetails<'_> {
+  fn values(&self) -> [(&'static str, Option<String>); 5] {
+    [
+      ("path", self.path.map(|path| path.to_string_lossy().into_owned())),
+      ("name", Some(self.name.to_string())),
+      ("size", self.size.map(|size| size.to_string())),
+      ("mime", Some(self.mime.to_string())),
+      ("sha256", self.sha256.map(str::to_string)),
+    ]
+  }
+}
+
+// Starts `--exec` for one file; its stdin takes the place of the output file.
+pub fn spawn_exec(template: &str, details: &FileDetails<'_>) -> Result<Child> {
+  command(template, details)
+    .stdin(Stdio::piped())
+    .spawn()
+    .with_context(|| format!("start --exec command `{template}`"))
+}
+
+pub async fn run_on_complete(template: &str, details: &FileDetails<'_>) -> Result<ExitStatus> {
+  command(template, details)
+    .stdin(Stdio::null())
+    .status()
+    .await
+    .with_context(|| format!("start --on-complete command `{template}`"))
+}
+
+// The error for a command that ran but did not succeed.
+pub fn failure(option: &str, status: ExitStatus) -> anyhow::Error {
+  let reason = match status.code() {
+    Some(code) => format!("exited with status {code}"),
+    None => "was terminated by a signal".to_string(),
+  };
+  ErrorKind::Hook.error(format!("{option} command {reason}"))
+}
+
+fn command(template: &str, details: &FileDetails<'_>) -> Command {
+  let values = details.values();
+  let mut command = shell(&expand(template, &values));
+  for (key, value) in values {
+    if let Some(value) = value {
+      command.env(format!("PAIRLANE_{}", key.to_ascii_uppercase()), value);
+    }
+  }
+  command
+}
+
+// One pass over the template, so a substituted value (which comes from the sender) is never
+// scanned for placeholders itself.
+fn expand(template: &str, values: &[(&'static str, Option<String>)]) -> String {
+  let mut line = String::with_capacity(template.len());
+  let mut rest = template;
+  while let Some(start) = rest.find('{') {
+    line.push_str(&rest[..start]);
+    rest = &rest[start..];
+    let placeholder = values.iter().find(|(key, _)| {
+      rest[1..].strip_prefix(key).is_some_and(|tail| tail.starts_with('}'))
+    });
+    match placeholder {
+      Some((key, value)) => {
+        line.push_str(&quote(value.as_deref().unwrap_or_default()));
+        rest = &rest[key.len() + 2..];
+      }
+      None => {
+        line.push('{');
+        rest = &rest[1..];
+      }
+    }
+  }
+  line.push_str(rest);
+  line
+}
+
+#[cfg(not(windows))]
+fn shell(line: &str) -> Command {
+  let mut command = Command::new("sh");
+  command.arg("-c").arg(line);
+  command
+}
+
+// The line goes to cmd as written: /S makes it strip just the outer quotes, /V:OFF keeps `!` literal
+// and /D skips AutoRun commands.
+#[cfg(windows)]
+fn shell(line: &str) -> Command {
+  let mut command = Command::new("cmd");
+  command.args(["/D", "/V:OFF", "/S", "/C"]).raw_arg(format!("\"{line}\""));
+  command
+}
+
+// File names come from the sender, so they must never reach the shell unquoted.
+#[cfg(not(windows))]
+fn quote(value: &str) -> String {
+  format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+// cmd expands `%` even inside quotes and ends the line at a newline, and neither can be escaped
+// there, so they become `_`; the PAIRLANE_* variables keep the exact value.
+#[cfg(windows)]
+fn quote(value: &str) -> String {
+  let value: String = value
+    .chars()
+    .map(|c| if c == '%' || c.is_control() { '_' } else { c })
+    .collect();
+  format!("\"{}\"", value.replace('"', "\"\""))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn values(path: Option<&str>, name: &str) -> [(&'static str, Option<String>); 5] {
+    FileDetails {
+      path: path.map(Path::new),
+      name,
+      size: Some(42),
+      mime: "text/plain",
+      sha256: None,
+    }
+    .values()
+  }
+
+  #[test]
+  fn expand_quotes_every_placeholder() {
+    let line = expand("wc -c {path} {size} {sha256}", &values(Some("/tmp/a b"), "a b"));
+    assert_eq!(line, format!("wc -c {} {} {}", quote("/tmp/a b"), quote("42"), quote("")));
+  }
+
+  #[test]
+  fn expand_leaves_other_braces_and_substituted_values_alone() {
+    let line = expand("echo {} {nope} {name", &values(None, "x"));
+    assert_eq!(line, "echo {} {nope} {name");
+    let line = expand("echo {name}", &values(Some("/secret"), "{path}"));
+    assert_eq!(line, format!("echo {}", quote("{path}")));
+  }
+
+  #[cfg(not(windows))]
+  #[test]
+  fn quote_survives_single_quotes() {
+    assert_eq!(quote("it's"), r"'it'\''s'");
+    assert_eq!(quote(""), "''");
+  }
+
+  #[cfg(windows)]
+  #[test]
+  fn quote_keeps_percent_and_line_breaks_from_cmd() {
+    assert_eq!(quote("50% \"off\"\r\n"), "\"50_ \"\"off\"\"__\"");
+  }
+
+  #[cfg(not(windows))]
+  #[tokio::test]
+  async fn a_hostile_name_reaches_the_command_as_text() {
+    let name = "x'; echo pwned; '$(id)`id`\n*";
+    let output = command("printf %s {name}", &FileDetails {
+      path: None,
+      name,
+      size: None,
+      mime: "text/plain",
+      sha256: None,
+    })
+    .output()
+    .await
+    .unwrap();
+    assert_eq!(String::from_utf8_lossy(&output.stdout), name);
+  }
+}
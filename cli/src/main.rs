@@ -1,24 +1,47 @@
 // Design: see README.md for the signaling flow; related to src/client/room.tsx.
 
-use aes_gcm::aead::{Aead, KeyInit};
+mod approval;
+mod code;
+mod error;
+mod events;
+mod hooks;
+mod progress;
+mod push;
+mod server;
+mod shutdown;
+mod signaling;
+
+use aes_gcm::aead::{Aead, KeyInit, Payload};
 use aes_gcm::{Aes256Gcm, Nonce};
 use anyhow::{anyhow, Context, Result};
 use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
 use bytes::Bytes;
-use clap::{Parser, Subcommand};
-use futures_util::{SinkExt, StreamExt};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use code::TransferCode;
+use error::{ErrorKind, ResultExt};
+use events::Event;
+use progress::{plural, ProgressRow};
+use futures_util::StreamExt;
 use getrandom::getrandom;
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use sha2::{Digest, Sha256};
+use spake2::{Ed25519Group, Identity, Password, Spake2};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::env;
 use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use tokio::fs::File;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::sync::{mpsc, Mutex};
-use tokio::time::{sleep, Duration};
-use tokio_tungstenite::connect_async;
+use std::io::{IsTerminal, SeekFrom};
+use tokio::fs::{File, OpenOptions};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::sync::{mpsc, Mutex, MutexGuard, Notify};
+use tokio::net::TcpStream;
+use tokio::time::{timeout, Duration};
 use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
 use url::form_urlencoded;
 use url::Url;
 use uuid::Uuid;
@@ -41,6 +64,43 @@ const AES_KEY_LEN: usize = 32;
 const AES_NONCE_LEN: usize = 12;
 const AES_TAG_LEN: usize = 16;
 const MAX_FRAME_SIZE: usize = 16 * 1024;
+// Version 2 of the `encrypted` meta field: STREAM framing with a per-file nonce prefix.
+const STREAM_VERSION: u8 = 2;
+const STREAM_PREFIX_LEN: usize = 7;
+const STREAM_CHUNK_SIZE: usize = MAX_FRAME_SIZE - AES_TAG_LEN;
+const HASH_BUFFER_SIZE: usize = 1024 * 1024;
+// Same flow control as the browser sender: pause above the high-water mark, resume at the low threshold.
+const BUFFER_HIGH_WATER: usize = 8 * 1024 * 1024;
+const BUFFER_LOW_THRESHOLD: usize = 4 * 1024 * 1024;
+const BUFFER_POLL_INTERVAL: Duration = Duration::from_millis(20);
+// Waiting on the send buffer only fails once it has stopped shrinking for this long.
+const BUFFER_STALL_TIMEOUT: Duration = Duration::from_secs(30);
+// Browsers never send `hello`, so the sender only waits briefly before falling back to the legacy flow.
+const HELLO_TIMEOUT: Duration = Duration::from_secs(2);
+const CONTROL_REPLY_TIMEOUT: Duration = Duration::from_secs(30);
+// Once the receiver says its command is running on a file, the ack may take as long as the command;
+// a connection that fails meanwhile is reported on its own.
+const HOOK_REPLY_TIMEOUT: Duration = Duration::from_secs(24 * 60 * 60);
+// A receiver with --confirm waits for a person to answer.
+const APPROVAL_TIMEOUT: Duration = Duration::from_secs(600);
+const AUTH_TIMEOUT: Duration = Duration::from_secs(10);
+const AUTH_NONCE_LEN: usize = 32;
+// HKDF info string for the key-proof subkey. AES-GCM keeps using the room key as is, since
+// browsers that predate the proof still encrypt with it.
+const AUTH_KEY_INFO: &[u8] = b"pairlane-auth-v1";
+const CODE_CLAIM_ATTEMPTS: usize = 8;
+// Peer ID used in place of a server-assigned client ID with --manual-signaling.
+const MANUAL_PEER_ID: &str = "manual";
+const DEFAULT_STUN_SERVER: &str = "stun:stun.cloudflare.com:3478";
+const FEATURE_RESUME: &str = "resume";
+const FEATURE_STREAM_AEAD: &str = "stream-v2";
+const FEATURE_ACK: &str = "ack";
+const FEATURE_UNSIZED: &str = "unsized";
+const FEATURE_APPROVE: &str = "approve";
+const FEATURE_PUSH: &str = "push";
+// `send -` reads the file from stdin; `receive -o -` writes it to stdout.
+const STDIO_PATH: &str = "-";
+const STDIN_NAME: &str = "stdin";
 
 // Design: see README.md and docs/signaling-protocol.md; related to Command and transfer helpers below.
 #[derive(Parser, Debug)]
@@ -49,40 +109,239 @@ const MAX_FRAME_SIZE: usize = 16 * 1024;
 struct Cli {
   #[command(subcommand)]
   command: Command,
+  #[arg(long, global = true, help = "Print one JSON event per line on stdout; logs go to stderr")]
+  json: bool,
 }
 
 #[derive(Subcommand, Debug)]
 enum Command {
   Send {
-    #[arg(value_name = "FILE", help = "File to send")]
-    file: Option<PathBuf>,
+    #[arg(
+      value_name = "PATH",
+      help = "Files or directories to send, optionally followed by a room ID, full room URL (supports #k=...) or code"
+    )]
+    paths: Vec<PathBuf>,
     #[arg(long = "file", value_name = "PATH", help = "File to send (legacy --file)", hide = true)]
     file_flag: Option<PathBuf>,
-    #[arg(value_name = "ROOM_ID_OR_URL", help = "Room ID or full room URL (optional, supports #k=...)")]
-    room_input: Option<String>,
     #[arg(long = "room-id", value_name = "ROOM_ID_OR_URL", help = "Room ID or full room URL (legacy --room-id)", hide = true)]
     room_id: Option<String>,
     #[arg(long, value_name = "URL", help = "Override signaling endpoint")]
     endpoint: Option<String>,
     #[arg(long, help = "Disable E2E encryption (default: enabled)")]
     no_encrypt: bool,
+    #[arg(
+      long,
+      conflicts_with_all = ["room_id", "no_encrypt", "stay_open"],
+      help = "Share a short code (e.g. 7-crossword-banana) instead of a URL carrying the key; good for one transfer",
+      long_help = "Share a short code (e.g. 7-crossword-banana) instead of a URL carrying the key; good for one transfer.\n\n\
+        The two words hold 16 bits, so a guess is right once in 65536 tries, and a wrong guess burns the code. \
+        The number only picks the room and is easy to guess: whoever takes it first can make the transfer fail, \
+        but cannot learn the words. Share the URL when that is not good enough."
+    )]
+    code: bool,
+    #[arg(
+      long,
+      conflicts_with_all = ["room_id", "code", "endpoint"],
+      help = "Skip the signaling server: print an offer to paste into the receiver, then paste its answer back"
+    )]
+    manual_signaling: bool,
+    #[arg(
+      long,
+      value_name = "N",
+      value_parser = clap::value_parser!(u32).range(1..=10),
+      conflicts_with_all = ["room_id", "code", "manual_signaling"],
+      help = "Let up to N receivers download at once from the room this sender creates (1-10, default 3)"
+    )]
+    max_concurrent: Option<u32>,
+    #[command(flatten)]
+    ice: IceArgs,
+    #[arg(long, value_name = "NAME", help = "File name to announce for a single file or - (default: stdin)")]
+    name: Option<String>,
+    #[arg(long, value_name = "TYPE", help = "MIME type to announce for a single file or - (default: guessed from the name)")]
+    mime: Option<String>,
     #[arg(long, help = "Keep running after a successful send")]
     stay_open: bool,
+    #[arg(
+      long,
+      requires = "stay_open",
+      conflicts_with = "manual_signaling",
+      help = "Read paths to send to every connected receiver from stdin"
+    )]
+    interactive: bool,
+    #[arg(
+      long,
+      value_name = "SOCKET",
+      requires = "stay_open",
+      conflicts_with = "manual_signaling",
+      help = "Take paths to send from this local Unix socket (`send PATH` or `status` per line)"
+    )]
+    control: Option<PathBuf>,
+    #[arg(
+      long,
+      value_name = "DIR",
+      requires = "stay_open",
+      conflicts_with = "manual_signaling",
+      help = "Send files that appear in this directory to every connected receiver"
+    )]
+    watch: Option<PathBuf>,
+    #[arg(
+      long,
+      value_name = "N",
+      value_parser = clap::value_parser!(u32).range(1..),
+      requires = "stay_open",
+      conflicts_with = "manual_signaling",
+      help = "Take no more than N receivers, then stop once they are served"
+    )]
+    max_receivers: Option<u32>,
+    #[arg(
+      long,
+      value_name = "DURATION",
+      value_parser = shutdown::parse_duration,
+      requires = "stay_open",
+      conflicts_with_all = ["until", "manual_signaling"],
+      help = "Stop taking receivers after DURATION (e.g. 90m, 2h) and stop once they are served"
+    )]
+    timeout: Option<Duration>,
+    #[arg(
+      long,
+      value_name = "TIME",
+      value_parser = shutdown::parse_until,
+      requires = "stay_open",
+      conflicts_with = "manual_signaling",
+      help = "Stop taking receivers at TIME (17:00, or an RFC 3339 date and time) and stop once they are served"
+    )]
+    until: Option<chrono::DateTime<chrono::Local>>,
+    #[arg(
+      long,
+      value_name = "DURATION",
+      value_parser = shutdown::parse_duration,
+      requires = "stay_open",
+      conflicts_with = "manual_signaling",
+      help = "Stop after DURATION with no transfer in flight"
+    )]
+    idle_timeout: Option<Duration>,
   },
   Receive {
-    #[arg(value_name = "ROOM_ID_OR_URL", help = "Room ID or full room URL (supports #k=...)")]
+    #[arg(value_name = "ROOM_ID_OR_URL", help = "Room ID, full room URL (supports #k=...) or code from send --code")]
     room_input: Option<String>,
     #[arg(long = "room-id", value_name = "ROOM_ID_OR_URL", help = "Room ID or full room URL (legacy --room-id)", hide = true)]
     room_id: Option<String>,
-    #[arg(long, default_value = ".", value_name = "DIR", help = "Output directory")]
+    #[arg(short = 'o', long, default_value = ".", value_name = "DIR", help = "Output directory, or - to write a single file to stdout")]
     output_dir: PathBuf,
+    #[arg(
+      long,
+      value_name = "COMMAND",
+      conflicts_with = "output_dir",
+      help = "Pipe each file into the stdin of this shell command instead of saving it"
+    )]
+    exec: Option<String>,
+    #[arg(
+      long,
+      value_name = "COMMAND",
+      conflicts_with = "exec",
+      help = "Run this shell command after each verified file ({path}, {name}, {size}, {mime}, {sha256})"
+    )]
+    on_complete: Option<String>,
+    #[arg(long, value_enum, default_value_t = OnConflict::Rename, value_name = "POLICY", help = "What to do when a received file already exists")]
+    on_conflict: OnConflict,
+    #[arg(long, help = "Show what the sender offers and ask before accepting it")]
+    confirm: bool,
+    #[arg(
+      long,
+      value_name = "RULE",
+      conflicts_with = "confirm",
+      help = "Accept only offers matching RULE, e.g. 'size<1G && mime=image/*'; decline the rest"
+    )]
+    accept_if: Option<approval::Rule>,
+    #[arg(long, value_name = "SIZE", value_parser = approval::parse_size, help = "Decline files larger than SIZE (e.g. 500M, 2G)")]
+    max_size: Option<u64>,
+    #[arg(
+      long,
+      value_name = "TYPE",
+      value_delimiter = ',',
+      help = "Accept only these MIME types (e.g. image/*,application/pdf); repeatable"
+    )]
+    allow_mime: Vec<String>,
+    #[arg(long, value_name = "EXT", value_delimiter = ',', help = "Decline files with these extensions (e.g. exe,bat); repeatable")]
+    deny_ext: Vec<String>,
     #[arg(long, value_name = "URL", help = "Override signaling endpoint")]
     endpoint: Option<String>,
     #[arg(long, value_name = "KEY", help = "Base64url decryption key (overrides #k=...)")]
     key: Option<String>,
+    #[arg(
+      long,
+      conflicts_with_all = ["room_input", "room_id", "endpoint"],
+      help = "Skip the signaling server: paste the sender's offer, then give the printed answer back to it"
+    )]
+    manual_signaling: bool,
+    #[command(flatten)]
+    ice: IceArgs,
     #[arg(long, help = "Keep running after a successful receive")]
     stay_open: bool,
   },
+  Room {
+    #[command(subcommand)]
+    command: RoomCommand,
+  },
+  Server {
+    #[arg(long, default_value = "127.0.0.1:8787", value_name = "ADDR", help = "Address to listen on")]
+    listen: std::net::SocketAddr,
+  },
+}
+
+#[derive(Subcommand, Debug)]
+enum RoomCommand {
+  Info {
+    #[arg(value_name = "ROOM_ID_OR_URL", help = "Room ID, full room URL or code from send --code")]
+    room_input: String,
+    #[arg(long, value_name = "URL", help = "Override signaling endpoint")]
+    endpoint: Option<String>,
+  },
+}
+
+// ICE servers come from --ice-server, else PAIRLANE_ICE_SERVERS, else `iceServers` in the config
+// file, else the public STUN server; --no-stun leaves only host candidates. TURN credentials for the
+// first two come from --ice-username plus --ice-credential or PAIRLANE_ICE_CREDENTIAL, so the password
+// can stay out of the process list.
+#[derive(Args, Debug)]
+struct IceArgs {
+  #[arg(long = "ice-server", value_name = "URL", help = "STUN/TURN server, repeatable (turn:host:3478)")]
+  ice_servers: Vec<String>,
+  #[arg(long, value_name = "USER", help = "Username for TURN servers given without one")]
+  ice_username: Option<String>,
+  #[arg(
+    long,
+    value_name = "PASS",
+    requires = "ice_username",
+    help = "Password for those TURN servers; PAIRLANE_ICE_CREDENTIAL keeps it off the command line"
+  )]
+  ice_credential: Option<String>,
+  #[arg(long, conflicts_with = "ice_servers", help = "Use no STUN/TURN servers (host candidates only, for LAN use)")]
+  no_stun: bool,
+}
+
+// `~/.config/pairlane/config.json`, or the file named by PAIRLANE_CONFIG.
+#[derive(Debug, Default, Deserialize)]
+struct ConfigFile {
+  #[serde(default, rename = "iceServers")]
+  ice_servers: Option<Vec<IceServerConfig>>,
+}
+
+// Same shape as the browser's RTCIceServer.
+#[derive(Debug, Deserialize)]
+struct IceServerConfig {
+  urls: IceUrls,
+  #[serde(default)]
+  username: String,
+  #[serde(default)]
+  credential: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum IceUrls {
+  One(String),
+  Many(Vec<String>),
 }
 
 #[derive(Debug, Deserialize)]
@@ -98,6 +357,8 @@ enum ServerMessage {
   Start { #[serde(rename = "peerId")] peer_id: Option<String> },
   #[serde(rename = "peer-left")]
   PeerLeft { #[serde(rename = "peerId")] peer_id: String },
+  #[serde(rename = "closing")]
+  Closing { reason: String },
   #[serde(rename = "offer")]
   Offer { from: String, sid: u64, sdp: RTCSessionDescription },
   #[serde(rename = "answer")]
@@ -117,6 +378,19 @@ enum ClientMessage {
   Candidate { to: String, sid: u64, candidate: RTCIceCandidateInit },
   #[serde(rename = "transfer-done")]
   TransferDone { #[serde(rename = "peerId")] peer_id: String },
+  #[serde(rename = "peer-rejected")]
+  PeerRejected {
+    #[serde(rename = "peerId")]
+    peer_id: String,
+    reason: String,
+  },
+  // The sender takes no more receivers; `peer_id` is one it was started with but turns away.
+  #[serde(rename = "closing")]
+  Closing {
+    reason: String,
+    #[serde(rename = "peerId", skip_serializing_if = "Option::is_none")]
+    peer_id: Option<String>,
+  },
 }
 
 #[derive(Debug, Deserialize)]
@@ -125,34 +399,243 @@ enum DataMessage {
   #[serde(rename = "meta")]
   Meta {
     name: String,
-    size: u64,
+    // Absent or null for a stream of unknown length; only `done` ends it.
+    #[serde(default)]
+    size: Option<u64>,
     mime: String,
-    encrypted: bool,
+    encrypted: EncryptedField,
+    #[serde(default, rename = "noncePrefix")]
+    nonce_prefix: Option<String>,
+    #[serde(default)]
+    sha256: Option<String>,
   },
+  #[serde(rename = "manifest")]
+  Manifest { files: Vec<ManifestEntry> },
+  // Any of the other messages, encrypted with the room key (base64url `[12-byte IV][ciphertext]`).
+  #[serde(rename = "sealed")]
+  Sealed { data: String },
   #[serde(rename = "done")]
-  Done,
+  Done {
+    #[serde(default)]
+    sha256: Option<String>,
+  },
+  // SPAKE2 message (base64url) when sending with a code; comes before `challenge`.
+  #[serde(rename = "pake")]
+  Pake { msg: String },
+  // Sent in the clear before anything else; the receiver must answer with a `proof`.
+  #[serde(rename = "challenge")]
+  Challenge { nonce: String },
+  #[serde(rename = "rejected")]
+  Rejected { reason: String },
+}
+
+// Browsers send `encrypted: true` (version 1, random nonce per frame); the CLI sends a version number.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum EncryptedField {
+  Flag(bool),
+  Version(u8),
+}
+
+impl EncryptedField {
+  fn version(&self) -> u8 {
+    match self {
+      EncryptedField::Flag(flag) => u8::from(*flag),
+      EncryptedField::Version(version) => *version,
+    }
+  }
+}
+
+// Receiver -> sender messages on the data channel. Only the CLI receiver sends these; the
+// sender treats a peer that never says `hello` as a browser and uses the legacy flow.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type")]
+enum ControlMessage {
+  #[serde(rename = "hello")]
+  Hello { features: Vec<String> },
+  #[serde(rename = "resume")]
+  Resume { offset: u64 },
+  #[serde(rename = "pake")]
+  Pake { msg: String },
+  // base64url HMAC-SHA256 over the challenge nonce, keyed with the room key (see `key_proof`).
+  #[serde(rename = "proof")]
+  Proof { mac: String },
+  // Reply to the manifest when the receiver asked for `approve`; nothing is sent until it arrives.
+  #[serde(rename = "approval")]
+  Approval {
+    accepted: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    reason: Option<String>,
+  },
+  // Sent before `ack` when the receiver's --exec or --on-complete command is still working on the file.
+  #[serde(rename = "running")]
+  Running,
+  // Reply to every `done`, in file order, once the file is on disk (or has been discarded).
+  #[serde(rename = "ack")]
+  Ack {
+    bytes: u64,
+    verified: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    // Exit status of the receiver's --exec or --on-complete command, when one ran.
+    #[serde(default, rename = "exitCode", skip_serializing_if = "Option::is_none")]
+    exit_code: Option<i32>,
+  },
+}
+
+// What `finish_file` needs of a file once it is out of `ReceiveProgress`.
+struct FinishedFile {
+  session: u64,
+  path: PathBuf,
+  part_path: Option<PathBuf>,
+  file: Option<Box<dyn AsyncWrite + Unpin + Send>>,
+  child: Option<tokio::process::Child>,
+  // Outcome of the size, hash and framing checks.
+  result: Result<()>,
+  conflict: OnConflict,
+  skipped: bool,
+  to_stdout: bool,
+  on_complete: Option<String>,
+  name: String,
+  mime: String,
+  received: u64,
+  hash: String,
+}
+
+struct AckReply {
+  bytes: u64,
+  verified: bool,
+  error: Option<String>,
+  exit_code: Option<i32>,
+}
+
+// Per-peer sending state, set up when the data channel opens.
+struct PeerLink {
+  peer_id: String,
+  buffer_low: Arc<Notify>,
+  progress: ProgressRow,
+  features: PeerFeatures,
+}
+
+// Reported by each offerer peer when it is finished with its receiver, successfully or not.
+struct PeerOutcome {
+  peer_id: String,
+  result: Result<()>,
+}
+
+// Sender side of the receiver's control messages. Messages that arrive before someone asks for
+// them (e.g. `hello` while waiting for `proof`) are kept until they are asked for.
+struct PeerControl {
+  rx: mpsc::UnboundedReceiver<ControlMessage>,
+  stash: VecDeque<ControlMessage>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ManifestEntry {
+  name: String,
+  // Null for a stream of unknown length.
+  size: Option<u64>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  mime: Option<String>,
 }
 
 struct RoomInput {
   room_id: String,
   endpoint: Option<String>,
   key: Option<Vec<u8>>,
+  code: Option<TransferCode>,
+}
+
+type SignalingStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+// What the two sides paste to each other with --manual-signaling, as one base64url JSON line.
+// The SDP is taken after ICE gathering finishes, so it already carries every candidate.
+#[derive(Serialize, Deserialize)]
+struct ManualSignal {
+  sdp: RTCSessionDescription,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  room: Option<String>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  key: Option<String>,
 }
 
 #[derive(Clone)]
 struct FileInfo {
+  // `-` for stdin.
   path: PathBuf,
+  // Relative path with `/` separators; a plain file name for single files.
   name: String,
-  size: u64,
+  // The size of stdin is only known once it has been sent.
+  size: Option<u64>,
   mime: String,
 }
 
+// `--name` / `--mime`, which apply to a single file or stdin.
+#[derive(Default)]
+struct FileOverrides {
+  name: Option<String>,
+  mime: Option<String>,
+}
+
+struct SendContext {
+  room_id: String,
+  files: Vec<FileInfo>,
+  room_key: Option<Vec<u8>>,
+  crypto: Option<Arc<Aes256Gcm>>,
+  ice_servers: Vec<RTCIceServer>,
+  // With a code there is no room key; each peer's key comes out of a PAKE run with this password.
+  code: Option<String>,
+  // Stdin can be read once, so only the first receiver gets it.
+  stdin_taken: AtomicBool,
+  // Slots for `--max-receivers`, taken once a receiver passes the key check.
+  admissions: shutdown::Admissions,
+}
+
+#[derive(Default)]
+struct PeerFeatures {
+  resume: bool,
+  stream_aead: bool,
+  ack: bool,
+  unknown_length: bool,
+  approve: bool,
+  push: bool,
+}
+
+// STREAM construction: nonce = 7-byte random prefix || 32-bit big-endian frame counter || last-frame
+// flag, with the file metadata and room ID as associated data. A dropped, duplicated or reordered
+// frame fails to decrypt, and a stream that ends before the flagged frame is detected as truncated.
+struct StreamCipher {
+  crypto: Arc<Aes256Gcm>,
+  prefix: [u8; STREAM_PREFIX_LEN],
+  aad: Vec<u8>,
+  counter: u32,
+  finished: bool,
+}
+
 struct OffererPeerState {
   signal_sid: u64,
   active_sid: Option<u64>,
   pending_candidates: Vec<PendingCandidate>,
   remote_desc_set: bool,
   sending: bool,
+  // For `status` with --stay-open: files pushed to this receiver but not yet acknowledged, files it
+  // has acknowledged, and what it is up to.
+  queued: usize,
+  delivered: usize,
+  activity: PeerActivity,
+}
+
+enum PeerActivity {
+  Connecting,
+  Sending,
+  Idle,
+  // The receiver takes no more files on this connection (a browser, or a CLI without --stay-open).
+  Finished,
+  // The receiver left the room without a transfer failing.
+  Left,
+  // Passed the key check after every `--max-receivers` slot was taken.
+  TurnedAway,
+  Failed(String),
 }
 
 struct PendingCandidate {
@@ -164,6 +647,8 @@ struct OffererPeer {
   peer_id: String,
   pc: Arc<RTCPeerConnection>,
   state: Arc<Mutex<OffererPeerState>>,
+  // Files pushed with --stay-open, sent one batch at a time after the first transfer.
+  queue: mpsc::UnboundedSender<Vec<FileInfo>>,
 }
 
 struct ReceiverState {
@@ -174,76 +659,450 @@ struct ReceiverState {
   remote_desc_set: bool,
 }
 
+// What the receiver does with an offer: `--confirm`/`--accept-if`, the limits, `--exec`,
+// `--on-complete` and `--on-conflict`, and whether it waits for more with `--stay-open`.
+struct ReceiveOptions {
+  approval: approval::Approval,
+  // Shared by `--confirm` and `--on-conflict ask`; stdin is only read once one of them asks.
+  prompter: Arc<approval::Prompter>,
+  limits: approval::Limits,
+  exec: Option<String>,
+  on_complete: Option<String>,
+  on_conflict: OnConflict,
+  stay_open: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OnConflict {
+  // Save as `name (1).ext`, `name (2).ext`, ...
+  Rename,
+  Overwrite,
+  // Keep the existing file; the incoming one is still checked, then discarded.
+  Skip,
+  // Prompt on the terminal for each file.
+  Ask,
+}
+
 struct ReceiveProgress {
   output_dir: PathBuf,
+  options: ReceiveOptions,
+  // The answer to this sender's offer, once given.
+  approved: Option<bool>,
+  // Sanitized names and sizes from the approved manifest. Each meta must match an entry not used
+  // yet; without a manifest, every meta is judged on its own.
+  manifest: Option<Vec<(String, Option<u64>)>>,
   current_file: Option<PathBuf>,
-  file: Option<File>,
-  expected_size: u64,
+  // Relative name and MIME type from the current file's meta, for the hooks.
+  current_name: String,
+  current_mime: String,
+  // Data is written to `<name>.<hash>.part` and renamed to `current_file` once verified; with
+  // `-o -` it goes straight to stdout and with `--exec` into the command's stdin, without a part file.
+  part_file: Option<PathBuf>,
+  file: Option<Box<dyn AsyncWrite + Unpin + Send>>,
+  // `--on-conflict` as settled for the current file (never `Ask`), and whether it is being
+  // discarded because the existing file is kept.
+  conflict: OnConflict,
+  skipped: bool,
+  child: Option<tokio::process::Child>,
+  to_stdout: bool,
+  // None for a stream of unknown length.
+  expected_size: Option<u64>,
   received: u64,
+  expected_files: usize,
+  completed_files: usize,
+  hasher: Sha256,
+  // First failure while receiving the current file; reported when `done` arrives.
+  error: Option<anyhow::Error>,
   encrypted: bool,
+  stream: Option<StreamCipher>,
+  // Set once the sender asked for the key proof. Only the CLI does, and it uses stream-v2 with a
+  // receiver that lists it in `hello` (we always do), so an older version from it means `hello` was
+  // tampered with on the way.
+  stream_required: bool,
+  room_id: String,
+  room_key: Option<Vec<u8>>,
   crypto: Option<Arc<Aes256Gcm>>,
-  success_tx: Option<mpsc::UnboundedSender<()>>,
+  code: Option<String>,
+  // Each transfer reports its outcome once through `outcome_tx`, which is re-armed from `outcomes`
+  // for the next transfer and the next connection.
+  outcomes: mpsc::UnboundedSender<Result<()>>,
+  outcome_tx: Option<mpsc::UnboundedSender<Result<()>>>,
+  // Bumped for every peer connection, so callbacks from a connection that was replaced stay quiet.
+  session: u64,
+  summary: ReceiveSummary,
+  display: ProgressRow,
+}
+
+// Running totals across every transfer this receiver has taken part in.
+#[derive(Default)]
+struct ReceiveSummary {
+  transfers: usize,
+  failed: usize,
+  files: usize,
+  skipped: usize,
+  bytes: u64,
+}
+
+impl ReceiveProgress {
+  fn new(
+    output_dir: &Path,
+    options: ReceiveOptions,
+    room_id: &str,
+    room_key: Option<Vec<u8>>,
+    crypto: Option<Arc<Aes256Gcm>>,
+    code: Option<String>,
+    outcomes: mpsc::UnboundedSender<Result<()>>,
+  ) -> Self {
+    ReceiveProgress {
+      output_dir: output_dir.to_path_buf(),
+      options,
+      approved: None,
+      manifest: None,
+      current_file: None,
+      current_name: String::new(),
+      current_mime: String::new(),
+      part_file: None,
+      file: None,
+      conflict: OnConflict::Rename,
+      skipped: false,
+      child: None,
+      to_stdout: output_dir.as_os_str() == STDIO_PATH,
+      expected_size: None,
+      received: 0,
+      expected_files: 1,
+      completed_files: 0,
+      hasher: Sha256::new(),
+      error: None,
+      encrypted: false,
+      stream: None,
+      stream_required: false,
+      room_id: room_id.to_string(),
+      room_key,
+      crypto,
+      code,
+      outcome_tx: Some(outcomes.clone()),
+      outcomes,
+      session: 0,
+      summary: ReceiveSummary::default(),
+      display: ProgressRow::new("[recv]", None),
+    }
+  }
+
+  // Whether losing the connection now fails a transfer. With --stay-open a connection that ends
+  // between transfers is not a failure; the receiver just waits for the next one.
+  fn transfer_underway(&self) -> bool {
+    self.outcome_tx.is_some() && (self.approved == Some(true) || !self.options.stay_open)
+  }
+
+  // Gets ready for the sender's next offer on the same connection. A declined sender stays declined
+  // until it reconnects.
+  fn next_transfer(&mut self) {
+    if self.approved == Some(true) {
+      self.approved = None;
+    }
+    self.manifest = None;
+    self.expected_files = 1;
+    self.completed_files = 0;
+    self.outcome_tx = Some(self.outcomes.clone());
+  }
+
+  // Forgets everything about the previous connection and returns the new session number. A partly
+  // received file keeps its `.part` so the sender can resume it.
+  fn next_session(&mut self) -> u64 {
+    self.file = None;
+    self.stream = None;
+    if let Some(mut child) = self.child.take() {
+      let _ = child.start_kill();
+    }
+    self.part_file = None;
+    if self.current_file.take().is_some() {
+      self.display.finish();
+    }
+    self.skipped = false;
+    self.received = 0;
+    self.expected_size = None;
+    self.hasher = Sha256::new();
+    self.error = None;
+    self.encrypted = false;
+    self.stream_required = false;
+    self.approved = None;
+    self.next_transfer();
+    self.session += 1;
+    self.session
+  }
+}
+
+impl ReceiveSummary {
+  fn record(&mut self, outcome: &Result<()>) {
+    self.transfers += 1;
+    if outcome.is_err() {
+      self.failed += 1;
+    }
+  }
+
+  fn describe(&self) -> String {
+      let mut text = format!(
+      "{} ({}) in {}",
+      plural(self.files, "file"),
+      progress::format_bytes(self.bytes),
+      plural(self.transfers, "transfer")
+    );
+    if self.skipped > 0 {
+      text.push_str(&format!(", {} skipped", self.skipped));
+    }
+    if self.failed > 0 {
+      text.push_str(&format!(", {} failed", self.failed));
+    }
+    text
+  }
 }
 
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn main() -> ExitCode {
   let cli = Cli::parse();
+  if cli.json {
+    events::enable();
+  }
+  match run(cli.command).await {
+    Ok(()) => ExitCode::SUCCESS,
+    Err(err) => {
+      events::emit(Event::Error {
+        code: error::code(&err),
+        message: format!("{err:#}"),
+        peer_id: None,
+      });
+      eprintln!("Error: {err:?}");
+      ExitCode::from(error::exit_code(&err))
+    }
+  }
+}
 
-  match cli.command {
+async fn run(command: Command) -> Result<()> {
+  match command {
     Command::Send {
-      file,
+      paths,
       file_flag,
-      room_input,
       room_id,
       endpoint,
       no_encrypt,
+      code,
+      manual_signaling,
+      max_concurrent,
+      ice,
+      name,
+      mime,
       stay_open,
+      interactive,
+      control,
+      watch,
+      max_receivers,
+      timeout,
+      until,
+      idle_timeout,
     } => {
-      let file = file_flag
-        .or(file)
-        .ok_or_else(|| anyhow!("File path is required (usage: send <FILE>)"))?;
-      let room_input = room_id.or(room_input);
-      run_send(room_input.as_deref(), &file, endpoint.as_deref(), no_encrypt, stay_open).await
+      let ice_servers = resolve_ice_servers(&ice)?;
+      let (mut paths, room_input) = split_send_inputs(paths)?;
+      paths.extend(file_flag);
+      let pushing = interactive || control.is_some() || watch.is_some();
+      if paths.is_empty() && !pushing {
+        return Err(anyhow!("File path is required (usage: send <PATH>...)"));
+      }
+      if paths.iter().any(|path| path.as_os_str() == STDIO_PATH) {
+        if paths.len() > 1 {
+          return Err(anyhow!("- (stdin) must be the only path"));
+        }
+        if manual_signaling || stay_open {
+          return Err(anyhow!("- (stdin) cannot be combined with --manual-signaling or --stay-open"));
+        }
+      }
+      if interactive && paths.iter().any(|path| path.as_os_str() == STDIO_PATH) {
+        return Err(anyhow!("- (stdin) cannot be combined with --interactive"));
+      }
+      // A sender that only pushes may start with nothing to send.
+      let files = if paths.is_empty() {
+        Vec::new()
+      } else {
+        collect_files(&paths, &FileOverrides { name, mime }).await?
+      };
+      if manual_signaling {
+        return run_send_manual(files, no_encrypt, ice_servers).await;
+      }
+      let room = match room_id.or(room_input) {
+        Some(_) if code => return Err(anyhow!("--code picks its own room; do not pass a room ID")),
+        Some(_) if max_concurrent.is_some() => {
+          return Err(anyhow!("--max-concurrent only applies to a room this sender creates; do not pass a room ID"))
+        }
+        Some(value) => SendRoom::Join(value),
+        None if code => SendRoom::Code,
+        None => SendRoom::Create(RoomOptions {
+          creator_cid: None,
+          max_concurrent,
+        }),
+      };
+      let stay_open = if stay_open {
+        let (command_tx, command_rx) = mpsc::unbounded_channel();
+        if let Some(dir) = watch {
+          push::spawn_watch(dir, command_tx.clone())?;
+        }
+        if let Some(path) = control.as_deref() {
+          push::spawn_control(path, command_tx.clone())?;
+        }
+        if interactive {
+          push::spawn_prompt(command_tx);
+        }
+        let deadline = match timeout {
+          Some(timeout) => Some(shutdown::deadline_after(timeout)?),
+          None => until,
+        };
+        Some(StayOpen {
+          commands: command_rx,
+          limits: shutdown::Limits {
+            max_receivers: max_receivers.map(|count| count as usize),
+            deadline,
+            idle_timeout,
+          },
+        })
+      } else {
+        None
+      };
+      run_send(room, files, endpoint.as_deref(), no_encrypt, ice_servers, stay_open).await
     }
     Command::Receive {
       room_input,
       room_id,
       output_dir,
+      exec,
+      on_complete,
+      on_conflict,
+      confirm,
+      accept_if,
+      max_size,
+      allow_mime,
+      deny_ext,
       endpoint,
       key,
+      manual_signaling,
+      ice,
       stay_open,
     } => {
+      if confirm && (events::enabled() || !std::io::stdin().is_terminal()) {
+        return Err(anyhow!("--confirm needs a terminal on stdin and cannot be combined with --json (use --accept-if)"));
+      }
+      if on_conflict == OnConflict::Ask && (events::enabled() || !std::io::stdin().is_terminal()) {
+        return Err(anyhow!("--on-conflict ask needs a terminal on stdin and cannot be combined with --json"));
+      }
+      let approval = match accept_if {
+        Some(rule) => approval::Approval::Rule(rule),
+        None if confirm => approval::Approval::Prompt,
+        None => approval::Approval::Auto,
+      };
+      let limits = approval::Limits {
+        max_size,
+        allow_mime,
+        deny_ext: deny_ext
+          .iter()
+          .map(|ext| ext.trim_start_matches('.').to_lowercase())
+          .collect(),
+      };
+      let options = ReceiveOptions {
+        approval,
+        prompter: Arc::new(approval::Prompter::default()),
+        limits,
+        exec,
+        on_complete,
+        on_conflict,
+        stay_open,
+      };
+      if output_dir.as_os_str() == STDIO_PATH {
+        if events::enabled() || stay_open {
+          return Err(anyhow!("-o - cannot be combined with --json or --stay-open"));
+        }
+        progress::reserve_stdout();
+      }
+      let ice_servers = resolve_ice_servers(&ice)?;
+      if manual_signaling {
+        return run_receive_manual(&output_dir, options, key.as_deref(), &ice_servers).await;
+      }
       let room_input = room_id
         .or(room_input)
         .ok_or_else(|| anyhow!("Room ID or URL is required (usage: receive <ROOM_ID_OR_URL>)"))?;
-      run_receive(&room_input, &output_dir, endpoint.as_deref(), key.as_deref(), stay_open).await
+      run_receive(
+        &room_input,
+        &output_dir,
+        options,
+        endpoint.as_deref(),
+        key.as_deref(),
+        &ice_servers,
+      )
+      .await
     }
+    Command::Room {
+      command: RoomCommand::Info { room_input, endpoint },
+    } => run_room_info(&room_input, endpoint.as_deref()).await,
+    Command::Server { listen } => server::run_server(listen).await,
   }
 }
 
+// Where `send` signals: a room it was given, a fresh code room, or a new room it creates.
+enum SendRoom {
+  Join(String),
+  Code,
+  Create(RoomOptions),
+}
+
+// `send --stay-open`: where pushed files come from (nowhere, when no source was given) and when to
+// stop.
+struct StayOpen {
+  commands: mpsc::UnboundedReceiver<push::Command>,
+  limits: shutdown::Limits,
+}
+
 async fn run_send(
-  room_id: Option<&str>,
-  file_path: &Path,
+  room: SendRoom,
+  files: Vec<FileInfo>,
   endpoint: Option<&str>,
   no_encrypt: bool,
-  stay_open: bool,
+  ice_servers: Vec<RTCIceServer>,
+  stay_open: Option<StayOpen>,
 ) -> Result<()> {
-  let file_info = load_file_info(file_path).await?;
+  let (stay_open, mut commands, limits) = match stay_open {
+    Some(StayOpen { commands, limits }) => (true, commands, limits),
+    None => (false, mpsc::unbounded_channel().1, shutdown::Limits::default()),
+  };
   let mut endpoint_override = endpoint.map(|value| value.to_string());
   let mut room_key: Option<Vec<u8>> = None;
+  let mut code: Option<TransferCode> = None;
+  let mut claimed_stream = None;
   let client_id = Uuid::new_v4().to_string();
-  let room_id = match room_id {
-    Some(value) => {
-      let parsed = parse_room_input(value)?;
+  let room_id = match room {
+    SendRoom::Join(value) => {
+      let parsed = parse_room_input(&value)?;
       if endpoint_override.is_none() {
         endpoint_override = parsed.endpoint;
       }
       room_key = parsed.key;
+      code = parsed.code;
       parsed.room_id
     }
-    None => create_room(endpoint_override.as_deref(), Some(&client_id)).await?,
+    SendRoom::Code => {
+      let (claimed, ws_stream) = claim_code_room(endpoint_override.as_deref(), &client_id).await?;
+      claimed_stream = Some(ws_stream);
+      let room_id = claimed.room_id();
+      code = Some(claimed);
+      room_id
+    }
+    SendRoom::Create(options) => {
+      let options = RoomOptions {
+        creator_cid: Some(client_id.clone()),
+        ..options
+      };
+      create_room(endpoint_override.as_deref(), &options).await?
+    }
   };
-  let encrypt = !no_encrypt;
+  if code.is_some() && no_encrypt {
+    return Err(anyhow!("Codes always encrypt; drop --no-encrypt"));
+  }
+  let encrypt = !no_encrypt && code.is_none();
   let room_key = if encrypt {
     Some(match room_key {
       Some(key) => key,
@@ -259,126 +1118,352 @@ async fn run_send(
   let ws_url = build_ws_url(endpoint_override.as_deref(), &room_id, &client_id)?;
 
   log_line("[room] id", &room_id);
-  log_line(
-    "[room] url",
-    &build_room_url_with_key(endpoint_override.as_deref(), &room_id, room_key.as_deref())?,
-  );
-  log_line("[ws] connecting", &ws_url.to_string());
-  let (ws_stream, _) = connect_async(ws_url.to_string())
-    .await
-    .context("connect signaling websocket")?;
-  let (mut ws_write, mut ws_read) = ws_stream.split();
-
-  let (signal_tx, mut signal_rx) = mpsc::unbounded_channel::<ClientMessage>();
-  let (success_tx, mut success_rx) = mpsc::unbounded_channel::<()>();
-  let success_tx = if stay_open { None } else { Some(success_tx) };
-
-  let writer = tokio::spawn(async move {
-    while let Some(msg) = signal_rx.recv().await {
-      let text = serde_json::to_string(&msg).map_err(|err| anyhow!(err))?;
-      ws_write.send(Message::Text(text)).await.map_err(|err| anyhow!(err))?;
+  match code.as_ref() {
+    Some(code) => {
+      log_line("[room] code", &format!("{code} (receive with: pairlane receive {code})"));
+      events::emit(Event::RoomCreated {
+        id: &room_id,
+        url: None,
+        code: Some(&code.to_string()),
+      });
     }
-    Ok::<(), anyhow::Error>(())
-  });
+    None => {
+      let url = build_room_url_with_key(endpoint_override.as_deref(), &room_id, room_key.as_deref())?;
+      log_line("[room] url", &url);
+      events::emit(Event::RoomCreated {
+        id: &room_id,
+        url: Some(&url),
+        code: None,
+      });
+    }
+  }
+  if files.is_empty() {
+    log_line("[send] files", "none yet; waiting for files to push");
+  } else {
+    log_line("[send] files", &describe_files(&files));
+  }
+  if let Some(limits) = limits.describe() {
+    log_line("[send] limits", &limits);
+  }
+  let ws_stream = match claimed_stream {
+    Some(ws_stream) => ws_stream,
+    None => {
+      log_line("[ws] connecting", ws_url.as_str());
+      let (ws_stream, _) = connect_async(ws_url.to_string())
+        .await
+        .context("connect signaling websocket")?;
+      ws_stream
+    }
+  };
+  let (signal_tx, signal_rx) = mpsc::unbounded_channel::<ClientMessage>();
+  let (outcome_tx, mut outcome_rx) = mpsc::unbounded_channel::<PeerOutcome>();
+  let (burn_tx, mut burn_rx) = mpsc::unbounded_channel::<String>();
+  let mut signaling = signaling::spawn(ws_url, "offerer", ws_stream, signal_rx);
 
   let peers: Arc<Mutex<HashMap<String, Arc<OffererPeer>>>> = Arc::new(Mutex::new(HashMap::new()));
-  let file_info = Arc::new(file_info);
+  let context = Arc::new(SendContext {
+    room_id: room_id.clone(),
+    files,
+    room_key,
+    crypto,
+    ice_servers,
+    code: code.map(|code| code.password()),
+    stdin_taken: AtomicBool::new(false),
+    admissions: shutdown::Admissions::new(limits.max_receivers),
+  });
 
+  // Without --stay-open the sender exits once no receiver is still in flight: successfully if one
+  // of them acknowledged everything, otherwise with the last receiver's failure.
+  let mut delivered = 0usize;
+  let mut failure: Option<anyhow::Error> = None;
+  let mut finished: HashSet<String> = HashSet::new();
   let mut completed = false;
+  // With --stay-open: every receiver started, in order, for the summary at the end.
+  let mut started: Vec<Arc<OffererPeer>> = Vec::new();
+  let mut stopping = Stopping::new(limits);
   loop {
     tokio::select! {
-      msg = ws_read.next() => {
-        let msg = match msg {
-          Some(msg) => msg.context("websocket read")?,
-          None => break,
+      text = signaling.recv() => {
+        let Some(text) = text else {
+          break;
+        };
+        stopping.activity();
+        let parsed: ServerMessage = match serde_json::from_str(&text) {
+          Ok(msg) => msg,
+          Err(_) => continue,
         };
-        if let Message::Text(text) = msg {
-          let parsed: ServerMessage = match serde_json::from_str(&text) {
-            Ok(msg) => msg,
-            Err(_) => continue,
-          };
 
-          match parsed {
-            ServerMessage::Role { role, cid } => {
-              log_line("[ws] role", &format!("{role} ({cid})"));
-              if role != "offerer" {
-                return Err(anyhow!("This command must be the offerer; connect first or use receive."));
-              }
+        match parsed {
+          ServerMessage::Role { role, cid } => {
+            log_line("[ws] role", &format!("{role} ({cid})"));
+            events::emit(Event::Role { role: &role, cid: &cid });
+            if role != "offerer" {
+              return Err(ErrorKind::Signaling.error("This command must be the offerer; connect first or use receive."));
             }
-            ServerMessage::Peers { count } => {
-              log_line("[ws] peers", &count.to_string());
+            stopping.rejoined(&signal_tx);
+          }
+          ServerMessage::Peers { count } => {
+            log_line("[ws] peers", &count.to_string());
+          }
+          ServerMessage::Wait { position } => {
+            let label = position.map(|p| p.to_string()).unwrap_or_else(|| "waiting".to_string());
+            log_line("[ws] queue", &label);
+            events::emit(Event::Queue { position });
+          }
+          // Started again after signaling dropped on either side. The room takes it for a new
+          // receiver, so it gets its slot back unless a transfer is still going.
+          ServerMessage::Start { peer_id: Some(peer_id) } if already_started(&started, &peer_id).await => {
+            log_line("[send] already started", &peer_id);
+            let busy = match started.iter().find(|peer| peer.peer_id == peer_id) {
+              Some(peer) => in_flight(&*peer.state.lock().await),
+              None => false,
+            };
+            if !busy {
+              let _ = signal_tx.send(ClientMessage::TransferDone { peer_id });
             }
-            ServerMessage::Wait { position } => {
-              let label = position.map(|p| p.to_string()).unwrap_or_else(|| "waiting".to_string());
-              log_line("[ws] queue", &label);
+          }
+          ServerMessage::Start { peer_id: Some(peer_id) } if stopping.closing.is_some() => {
+            stopping.turn_away(peer_id, &signal_tx);
+          }
+          ServerMessage::Start { peer_id: Some(peer_id) } => {
+            events::emit(Event::PeerJoined { peer_id: &peer_id });
+            let peer = create_offerer_peer(
+              peer_id.clone(),
+              signal_tx.clone(),
+              context.clone(),
+              outcome_tx.clone(),
+              Some(burn_tx.clone()),
+            )
+            .await?;
+            peers.lock().await.insert(peer_id.clone(), peer.clone());
+            match started.iter_mut().find(|known| known.peer_id == peer_id) {
+              Some(known) => *known = peer,
+              None => started.push(peer),
             }
-            ServerMessage::Start { peer_id } => {
-              if let Some(peer_id) = peer_id {
-                let peer = create_offerer_peer(
-                  peer_id.clone(),
-                  signal_tx.clone(),
-                  file_info.clone(),
-                  crypto.clone(),
-                  success_tx.clone(),
-                )
-                .await?;
-                peers.lock().await.insert(peer_id.clone(), peer);
-              }
+          }
+          ServerMessage::Answer { from, sid, sdp } => {
+            if let Some(peer) = peers.lock().await.get(&from).cloned() {
+              handle_answer(peer, sid, sdp).await?;
             }
-            ServerMessage::Answer { from, sid, sdp } => {
-              if let Some(peer) = peers.lock().await.get(&from).cloned() {
-                handle_answer(peer, sid, sdp).await?;
+          }
+          ServerMessage::Candidate { from, sid, candidate } => {
+            if let Some(peer) = peers.lock().await.get(&from).cloned() {
+              // Late candidates for a peer we already closed (e.g. rejected) are not fatal.
+              if let Err(err) = handle_offer_candidate(peer, sid, candidate).await {
+                log_line("[rtc] candidate error", &format!("{from}: {err:#}"));
               }
             }
-            ServerMessage::Candidate { from, sid, candidate } => {
-              if let Some(peer) = peers.lock().await.get(&from).cloned() {
-                handle_offer_candidate(peer, sid, candidate).await?;
+          }
+          // The receiver's websocket dropped but its peer connection did not; it will be back.
+          ServerMessage::PeerLeft { peer_id } if peer_connected(&peers, &peer_id).await => {
+            log_line("[ws] peer-left", &format!("{peer_id} (still connected)"));
+          }
+          ServerMessage::PeerLeft { peer_id } => {
+            log_line("[ws] peer-left", &peer_id);
+            events::emit(Event::PeerLeft { peer_id: &peer_id });
+            let removed = peers.lock().await.remove(&peer_id);
+            if let Some(peer) = removed {
+              let mut state = peer.state.lock().await;
+              if !matches!(state.activity, PeerActivity::Finished | PeerActivity::Failed(_) | PeerActivity::TurnedAway) {
+                state.activity = PeerActivity::Left;
               }
             }
-            ServerMessage::PeerLeft { peer_id } => {
-              log_line("[ws] peer-left", &peer_id);
-              peers.lock().await.remove(&peer_id);
-            }
-            _ => {}
           }
+          _ => {}
         }
       }
-      _ = success_rx.recv(), if !stay_open => {
-        log_line("[send] completed", "transfer done");
+      // A wrong guess burns the code, so nobody gets a second try at it.
+      Some(reason) = burn_rx.recv() => {
+        log_line("[send] code burned", &reason);
+        let _ = signal_tx.send(ClientMessage::Closing {
+          reason: "someone tried a wrong code".to_string(),
+          peer_id: None,
+        });
         let peers_snapshot = {
           let guard = peers.lock().await;
           guard.values().cloned().collect::<Vec<_>>()
         };
         for peer in peers_snapshot {
           let _ = peer.pc.close().await;
+          // The rejected peer's connection is already being closed by its own task; exiting
+          // before that finishes panics inside webrtc-rs.
+          let _ = timeout(Duration::from_secs(2), async {
+            while peer.pc.connection_state() != RTCPeerConnectionState::Closed {
+              tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+          })
+          .await;
         }
-        completed = true;
-        break;
+        signaling.close().await;
+        return Err(ErrorKind::Auth.error("Someone tried a wrong code, so it no longer works; send again for a new one."));
+      }
+      // Once closing, pushes wait unanswered until the sender exits and then hear it is shutting down.
+      Some(command) = commands.recv(), if stopping.closing.is_none() => {
+        stopping.activity();
+        let peers = peers.clone();
+        // Hashing a large file takes a while; signaling carries on meanwhile.
+        tokio::spawn(async move {
+          let answer = match command.request {
+            push::Request::Send(path) => push_files(&peers, &path).await.unwrap_or_else(|err| format!("{err:#}")),
+            push::Request::Status => peer_status(&peers).await,
+          };
+          match command.reply {
+            Some(reply) => {
+              let _ = reply.send(answer);
+            }
+            None => log_line("[push] watch", &answer),
+          }
+        });
       }
+      Some(outcome) = outcome_rx.recv() => {
+        stopping.activity();
+        match outcome.result {
+          Ok(()) => {
+            log_line("[send] acknowledged", &outcome.peer_id);
+            delivered += 1;
+          }
+          Err(err) => failure = Some(err),
+        }
+        finished.insert(outcome.peer_id);
+      }
+      stop = stopping.reached(&context.admissions, &peers), if stopping.closing.is_none() => {
+        stopping.close(stop, &signal_tx);
+      }
+    }
+    if stopping.closing.is_some() && !transfers_in_flight(&peers).await {
+      let peers_snapshot = {
+        let guard = peers.lock().await;
+        guard.values().cloned().collect::<Vec<_>>()
+      };
+      for peer in peers_snapshot {
+        let _ = peer.pc.close().await;
+      }
+      log_send_summary(&started).await;
+      events::emit(Event::Done);
+      signaling.close().await;
+      return Ok(());
+    }
+    let settled = !finished.is_empty() && peers.lock().await.keys().all(|peer_id| finished.contains(peer_id));
+    if !stay_open && settled && (delivered > 0 || failure.is_some()) {
+      let peers_snapshot = {
+        let guard = peers.lock().await;
+        guard.values().cloned().collect::<Vec<_>>()
+      };
+      for peer in peers_snapshot {
+        let _ = peer.pc.close().await;
+      }
+      if delivered == 0 {
+        signaling.abort();
+        return Err(failure.take().expect("checked above"));
+      }
+      log_line("[send] completed", "transfer done");
+      events::emit(Event::Done);
+      completed = true;
+      break;
     }
   }
   if completed {
-    writer.abort();
+    signaling.abort();
     return Ok(());
   }
-  drop(signal_tx);
-  writer.await??;
-  Ok(())
+  Err(signaling.error().await)
+}
+
+// When a `--stay-open` sender stops taking receivers: at its deadline, after an idle period, or once
+// every --max-receivers slot is taken. The room is told why, and so is any receiver it starts later.
+struct Stopping {
+  limits: shutdown::Limits,
+  last_activity: tokio::time::Instant,
+  // Why the sender stopped taking receivers, once it has.
+  closing: Option<String>,
+}
+
+impl Stopping {
+  fn new(limits: shutdown::Limits) -> Self {
+    Stopping {
+      limits,
+      last_activity: tokio::time::Instant::now(),
+      closing: None,
+    }
+  }
+
+  fn activity(&mut self) {
+    self.last_activity = tokio::time::Instant::now();
+  }
+
+  // Resolves with the first limit reached. It is not idle while a transfer is still going; it looks
+  // again a whole idle period later.
+  async fn reached(&mut self, admissions: &shutdown::Admissions, peers: &Mutex<HashMap<String, Arc<OffererPeer>>>) -> shutdown::Stop {
+    loop {
+      let next_stop = self.limits.next_stop(self.last_activity);
+      tokio::select! {
+        _ = tokio::time::sleep_until(next_stop.map_or_else(tokio::time::Instant::now, |(at, _)| at)), if next_stop.is_some() => {
+          match next_stop.map(|(_, stop)| stop) {
+            Some(shutdown::Stop::Idle(_)) if transfers_in_flight(peers).await => self.activity(),
+            Some(stop) => return stop,
+            None => {}
+          }
+        }
+        // A receiver passed the key check and took a --max-receivers slot.
+        _ = admissions.changed() => {
+          if let Some(stop) = admissions.full() {
+            return stop;
+          }
+        }
+      }
+    }
+  }
+
+  fn close(&mut self, stop: shutdown::Stop, signal_tx: &mpsc::UnboundedSender<ClientMessage>) {
+    let reason = stop.reason();
+    log_line("[send] stopping", &reason);
+    let _ = signal_tx.send(ClientMessage::Closing {
+      reason: reason.clone(),
+      peer_id: None,
+    });
+    self.closing = Some(reason);
+  }
+
+  // Back after a reconnect: the room forgot the sender was closing along with the old socket.
+  fn rejoined(&self, signal_tx: &mpsc::UnboundedSender<ClientMessage>) {
+    if let Some(reason) = &self.closing {
+      let _ = signal_tx.send(ClientMessage::Closing {
+        reason: reason.clone(),
+        peer_id: None,
+      });
+    }
+  }
+
+  // For a receiver the server started before it heard the sender is closing.
+  fn turn_away(&self, peer_id: String, signal_tx: &mpsc::UnboundedSender<ClientMessage>) {
+    log_line("[send] turned away", &peer_id);
+    let _ = signal_tx.send(ClientMessage::Closing {
+      reason: self.closing.clone().unwrap_or_default(),
+      peer_id: Some(peer_id),
+    });
+  }
 }
 
 async fn run_receive(
   room_input: &str,
   output_dir: &Path,
+  options: ReceiveOptions,
   endpoint: Option<&str>,
   key: Option<&str>,
-  stay_open: bool,
+  ice_servers: &[RTCIceServer],
 ) -> Result<()> {
+  let stay_open = options.stay_open;
   let parsed = parse_room_input(room_input)?;
   let mut key_override = parsed.key;
   if let Some(key) = key {
     key_override = Some(b64url_decode(key)?);
   }
   let endpoint_override = endpoint.or(parsed.endpoint.as_deref());
+  let code = parsed.code.map(|code| code.password());
+  if code.is_some() {
+    // The key is agreed with the sender over PAKE once the data channel is up.
+    key_override = None;
+  }
   let crypto = match key_override.as_deref() {
     Some(key) => Some(Arc::new(build_crypto(key)?)),
     None => None,
@@ -388,162 +1473,556 @@ async fn run_receive(
   let ws_url = build_ws_url(endpoint_override, &room_id, &client_id)?;
 
   log_line("[room] id", &room_id);
-  log_line("[ws] connecting", &ws_url.to_string());
+  log_line("[ws] connecting", ws_url.as_str());
   let (ws_stream, _) = connect_async(ws_url.to_string())
     .await
     .context("connect signaling websocket")?;
-  let (mut ws_write, mut ws_read) = ws_stream.split();
 
-  let (signal_tx, mut signal_rx) = mpsc::unbounded_channel::<ClientMessage>();
-  let (success_tx, mut success_rx) = mpsc::unbounded_channel::<()>();
-  let success_tx = if stay_open { None } else { Some(success_tx) };
-
-  let writer = tokio::spawn(async move {
-    while let Some(msg) = signal_rx.recv().await {
-      let text = serde_json::to_string(&msg).map_err(|err| anyhow!(err))?;
-      ws_write.send(Message::Text(text)).await.map_err(|err| anyhow!(err))?;
-    }
-    Ok::<(), anyhow::Error>(())
-  });
+  let (signal_tx, signal_rx) = mpsc::unbounded_channel::<ClientMessage>();
+  let (outcome_tx, mut outcome_rx) = mpsc::unbounded_channel::<Result<()>>();
+  let mut signaling = signaling::spawn(ws_url, "answerer", ws_stream, signal_rx);
 
   let receiver_state: Arc<Mutex<Option<ReceiverState>>> = Arc::new(Mutex::new(None));
-  let progress = Arc::new(Mutex::new(ReceiveProgress {
-    output_dir: output_dir.to_path_buf(),
-    current_file: None,
-    file: None,
-    expected_size: 0,
-    received: 0,
-    encrypted: false,
+  let progress = Arc::new(Mutex::new(ReceiveProgress::new(
+    output_dir,
+    options,
+    &room_id,
+    key_override,
     crypto,
-    success_tx,
-  }));
+    code,
+    outcome_tx,
+  )));
 
   let mut completed = false;
   loop {
     tokio::select! {
-      msg = ws_read.next() => {
-        let msg = match msg {
-          Some(msg) => msg.context("websocket read")?,
-          None => break,
+      text = signaling.recv() => {
+        let Some(text) = text else {
+          break;
         };
-        if let Message::Text(text) = msg {
-          let parsed: ServerMessage = match serde_json::from_str(&text) {
-            Ok(msg) => msg,
-            Err(_) => continue,
-          };
-
-          match parsed {
-            ServerMessage::Role { role, cid } => {
-              log_line("[ws] role", &format!("{role} ({cid})"));
-              if role != "answerer" {
-                return Err(anyhow!("This command must be the answerer; connect after the sender."));
-              }
-            }
-            ServerMessage::Peers { count } => {
-              log_line("[ws] peers", &count.to_string());
-            }
-            ServerMessage::Wait { position } => {
-              let label = position.map(|p| p.to_string()).unwrap_or_else(|| "waiting".to_string());
-              log_line("[ws] queue", &label);
-            }
-            ServerMessage::Start { .. } => {
-              let pc = create_peer_connection().await?;
-              let tx = signal_tx.clone();
-              let receiver_state_for_ice = receiver_state.clone();
-              pc.on_ice_candidate(Box::new(move |candidate| {
-                let tx = tx.clone();
-                let receiver_state = receiver_state_for_ice.clone();
-                Box::pin(async move {
-                  if let Some(candidate) = candidate {
-                    let candidate = candidate.to_json().unwrap_or_default();
-                    let guard = receiver_state.lock().await;
-                    if let Some(state) = guard.as_ref() {
-                      if let (Some(peer_id), Some(sid)) = (state.peer_id.clone(), state.active_sid) {
-                        let _ = tx.send(ClientMessage::Candidate { to: peer_id, sid, candidate });
-                      }
-                    }
-                  }
-                })
-              }));
-
-              let rx_progress = progress.clone();
-              pc.on_data_channel(Box::new(move |dc| {
-                let rx_progress = rx_progress.clone();
-                Box::pin(async move {
-                  wire_receiver_channel(dc, rx_progress).await;
-                })
-              }));
-
-              *receiver_state.lock().await = Some(ReceiverState {
-                pc,
-                peer_id: None,
-                active_sid: None,
-                pending_candidates: Vec::new(),
-                remote_desc_set: false,
-              });
-            }
-            ServerMessage::Offer { from, sid, sdp } => {
-              let mut guard = receiver_state.lock().await;
-              let state = guard.as_mut().ok_or_else(|| anyhow!("Receiver not initialized"))?;
-              state.peer_id = Some(from.clone());
-              state.active_sid = Some(sid);
-              state.pc.set_remote_description(sdp).await?;
-              state.remote_desc_set = true;
-              flush_receiver_candidates(state).await?;
-
-              let answer = state.pc.create_answer(None).await?;
-              state.pc.set_local_description(answer).await?;
-              if let Some(local) = state.pc.local_description().await {
-                let _ = signal_tx.send(ClientMessage::Answer { to: from, sid, sdp: local });
-              }
-            }
-            ServerMessage::Candidate { from: _, sid, candidate } => {
-              let mut guard = receiver_state.lock().await;
-              if let Some(state) = guard.as_mut() {
-                handle_receiver_candidate(state, sid, candidate).await?;
-              }
-            }
-            _ => {}
-          }
-        }
+        let parsed: ServerMessage = match serde_json::from_str(&text) {
+          Ok(msg) => msg,
+          Err(_) => continue,
+        };
+        handle_receiver_message(parsed, &receiver_state, &progress, &signal_tx, ice_servers).await?;
       }
-      _ = success_rx.recv(), if !stay_open => {
-        log_line("[recv] completed", "transfer done");
+      Some(outcome) = outcome_rx.recv() => {
+        if stay_open {
+          finish_transfer(&mut *progress.lock().await, outcome);
+          continue;
+        }
         if let Some(state) = receiver_state.lock().await.take() {
           let _ = state.pc.close().await;
         }
+        if let Err(err) = outcome {
+          signaling.abort();
+          return Err(err);
+        }
+        log_line("[recv] completed", "transfer done");
+        events::emit(Event::Done);
         completed = true;
         break;
       }
     }
   }
   if completed {
-    writer.abort();
+    signaling.abort();
     return Ok(());
   }
-  drop(signal_tx);
-  writer.await??;
-  Ok(())
+  if stay_open {
+    log_line("[recv] total", &progress.lock().await.summary.describe());
+  }
+  Err(signaling.error().await)
 }
 
-async fn create_offerer_peer(
-  peer_id: String,
-  signal_tx: mpsc::UnboundedSender<ClientMessage>,
-  file_info: Arc<FileInfo>,
-  crypto: Option<Arc<Aes256Gcm>>,
-  success_tx: Option<mpsc::UnboundedSender<()>>,
-) -> Result<Arc<OffererPeer>> {
-  let pc = create_peer_connection().await?;
-  let dc = pc
-    .create_data_channel(
-      "file",
-      Some(RTCDataChannelInit {
-        ordered: Some(true),
-        ..Default::default()
-      }),
+// Queues `path` for every receiver that still takes files, and says where it went.
+async fn push_files(peers: &Mutex<HashMap<String, Arc<OffererPeer>>>, path: &Path) -> Result<String> {
+  let files = collect_files(&[path.to_path_buf()], &FileOverrides::default()).await?;
+  let mut receivers = 0;
+  for peer in peers.lock().await.values() {
+    let mut state = peer.state.lock().await;
+    if matches!(state.activity, PeerActivity::Finished | PeerActivity::Failed(_) | PeerActivity::TurnedAway) {
+      continue;
+    }
+    if peer.queue.send(files.clone()).is_ok() {
+      state.queued += files.len();
+      receivers += 1;
+    }
+  }
+  let bytes: u64 = files.iter().filter_map(|file| file.size).sum();
+  Ok(format!(
+    "queued {} ({}, {}) for {}",
+    path.display(),
+    plural(files.len(), "file"),
+    progress::format_bytes(bytes),
+    plural(receivers, "receiver")
+  ))
+}
+
+// One line per receiver, for `status`.
+async fn peer_status(peers: &Mutex<HashMap<String, Arc<OffererPeer>>>) -> String {
+  let peers = peers.lock().await;
+  if peers.is_empty() {
+    return "no receivers connected".to_string();
+  }
+  let mut lines = Vec::new();
+  for peer in peers.values() {
+    lines.push(describe_peer(peer).await);
+  }
+  lines.sort();
+  lines.join("\n")
+}
+
+async fn describe_peer(peer: &OffererPeer) -> String {
+  let state = peer.state.lock().await;
+  let activity = match &state.activity {
+    PeerActivity::Connecting => "connecting".to_string(),
+    PeerActivity::Sending => "sending".to_string(),
+    PeerActivity::Idle => "idle".to_string(),
+    PeerActivity::Finished => "done, takes no more files".to_string(),
+    PeerActivity::Left => "left".to_string(),
+    PeerActivity::TurnedAway => "turned away, every slot was taken".to_string(),
+    PeerActivity::Failed(reason) => format!("failed: {reason}"),
+  };
+  format!(
+    "{}: {} delivered, {} queued, {activity}",
+    peer.peer_id, state.delivered, state.queued
+  )
+}
+
+// Whether the peer connection to `peer_id` is up. Signaling that comes back after a drop leaves it
+// alone.
+async fn peer_connected(peers: &Mutex<HashMap<String, Arc<OffererPeer>>>, peer_id: &str) -> bool {
+  peers
+    .lock()
+    .await
+    .get(peer_id)
+    .is_some_and(|peer| peer.pc.connection_state() == RTCPeerConnectionState::Connected)
+}
+
+// Whether a receiver is still being connected to or sent to. A receiver waiting for the next push
+// is not.
+async fn transfers_in_flight(peers: &Mutex<HashMap<String, Arc<OffererPeer>>>) -> bool {
+  for peer in peers.lock().await.values() {
+    if in_flight(&*peer.state.lock().await) {
+      return true;
+    }
+  }
+  false
+}
+
+fn in_flight(state: &OffererPeerState) -> bool {
+  match state.activity {
+    PeerActivity::Connecting | PeerActivity::Sending => true,
+    PeerActivity::Idle => state.queued > 0,
+    _ => false,
+  }
+}
+
+// A receiver the server starts a second time: its connection is still up, or it already took all
+// it takes. Starting it over would send it the whole batch again.
+async fn already_started(started: &[Arc<OffererPeer>], peer_id: &str) -> bool {
+  let Some(peer) = started.iter().find(|peer| peer.peer_id == peer_id) else {
+    return false;
+  };
+  peer.pc.connection_state() == RTCPeerConnectionState::Connected
+    || matches!(peer.state.lock().await.activity, PeerActivity::Finished)
+}
+
+// What every receiver started since the sender came up got, when a --stay-open sender stops.
+async fn log_send_summary(started: &[Arc<OffererPeer>]) {
+  let mut files = 0;
+  let mut receivers = 0;
+  let mut failed = 0;
+  for peer in started {
+    log_line("[send] receiver", &describe_peer(peer).await);
+    let state = peer.state.lock().await;
+    files += state.delivered;
+    match state.activity {
+      PeerActivity::TurnedAway => continue,
+      PeerActivity::Failed(_) => failed += 1,
+      _ => {}
+    }
+    receivers += 1;
+  }
+  log_line(
+    "[send] total",
+    &format!("{} delivered to {}, {failed} failed", plural(files, "file"), plural(receivers, "receiver")),
+  );
+}
+
+// --manual-signaling: the offer/answer exchange goes through the user's clipboard instead of a
+// server, reusing the same peer setup as the websocket path.
+async fn run_send_manual(files: Vec<FileInfo>, no_encrypt: bool, ice_servers: Vec<RTCIceServer>) -> Result<()> {
+  let room_id = format!("manual-{}", &Uuid::new_v4().simple().to_string()[..10]);
+  let room_key = if no_encrypt { None } else { Some(generate_key()?.to_vec()) };
+  let crypto = match room_key.as_deref() {
+    Some(key) => Some(Arc::new(build_crypto(key)?)),
+    None => None,
+  };
+  log_line("[send] files", &describe_files(&files));
+  let context = Arc::new(SendContext {
+    room_id: room_id.clone(),
+    files,
+    room_key: room_key.clone(),
+    crypto,
+    ice_servers,
+    code: None,
+    stdin_taken: AtomicBool::new(false),
+    admissions: shutdown::Admissions::default(),
+  });
+
+  let (signal_tx, mut signal_rx) = mpsc::unbounded_channel::<ClientMessage>();
+  let (outcome_tx, mut outcome_rx) = mpsc::unbounded_channel::<PeerOutcome>();
+  let peer = create_offerer_peer(MANUAL_PEER_ID.to_string(), signal_tx, context, outcome_tx, None).await?;
+  let sid = loop {
+    match signal_rx.recv().await {
+      Some(ClientMessage::Offer { sid, .. }) => break sid,
+      Some(_) => continue,
+      None => return Err(anyhow!("offer was not created")),
+    }
+  };
+  let offer = ManualSignal {
+    sdp: gathered_description(&peer.pc).await?,
+    room: Some(room_id),
+    key: room_key.as_deref().map(b64url_encode),
+  };
+  log_line("[manual] offer", "paste the next line into `pairlane receive --manual-signaling`");
+  print_manual_signal("offer", &offer)?;
+  log_line("[manual] waiting", "paste the receiver's answer and press Enter");
+  let answer = read_manual_signal().await?;
+  handle_answer(peer.clone(), sid, answer.sdp).await?;
+
+  let outcome = outcome_rx.recv().await;
+  let _ = peer.pc.close().await;
+  outcome.ok_or_else(|| anyhow!("sender stopped before the transfer finished"))?.result?;
+  log_line("[send] completed", "transfer done");
+  events::emit(Event::Done);
+  Ok(())
+}
+
+async fn receiver_connected(receiver_state: &Mutex<Option<ReceiverState>>) -> bool {
+  receiver_state
+    .lock()
+    .await
+    .as_ref()
+    .is_some_and(|state| state.pc.connection_state() == RTCPeerConnectionState::Connected)
+}
+
+async fn run_receive_manual(
+  output_dir: &Path,
+  options: ReceiveOptions,
+  key: Option<&str>,
+  ice_servers: &[RTCIceServer],
+) -> Result<()> {
+  let stay_open = options.stay_open;
+  log_line("[manual] waiting", "paste the sender's offer and press Enter");
+  let offer = read_manual_signal().await?;
+  let room_id = offer.room.unwrap_or_else(|| MANUAL_PEER_ID.to_string());
+  let room_key = match key.or(offer.key.as_deref()) {
+    Some(key) => Some(b64url_decode(key)?),
+    None => None,
+  };
+  let crypto = match room_key.as_deref() {
+    Some(key) => Some(Arc::new(build_crypto(key)?)),
+    None => None,
+  };
+  let (signal_tx, _signal_rx) = mpsc::unbounded_channel::<ClientMessage>();
+  let (outcome_tx, mut outcome_rx) = mpsc::unbounded_channel::<Result<()>>();
+  let receiver_state: Arc<Mutex<Option<ReceiverState>>> = Arc::new(Mutex::new(None));
+  let progress = Arc::new(Mutex::new(ReceiveProgress::new(
+    output_dir,
+    options,
+    &room_id,
+    room_key,
+    crypto,
+    None,
+    outcome_tx,
+  )));
+
+  let start = ServerMessage::Start { peer_id: None };
+  handle_receiver_message(start, &receiver_state, &progress, &signal_tx, ice_servers).await?;
+  let offer = ServerMessage::Offer {
+    from: MANUAL_PEER_ID.to_string(),
+    sid: 1,
+    sdp: offer.sdp,
+  };
+  handle_receiver_message(offer, &receiver_state, &progress, &signal_tx, ice_servers).await?;
+  let pc = receiver_state
+    .lock()
+    .await
+    .as_ref()
+    .map(|state| state.pc.clone())
+    .ok_or_else(|| anyhow!("Receiver not initialized"))?;
+  let answer = ManualSignal {
+    sdp: gathered_description(&pc).await?,
+    room: None,
+    key: None,
+  };
+  log_line("[manual] answer", "paste the next line back into the sender");
+  print_manual_signal("answer", &answer)?;
+
+  let outcome = loop {
+    match outcome_rx.recv().await {
+      Some(outcome) if stay_open => finish_transfer(&mut *progress.lock().await, outcome),
+      outcome => break outcome,
+    }
+  };
+  let _ = pc.close().await;
+  if let Some(Err(err)) = outcome {
+    return Err(err);
+  }
+  log_line("[recv] completed", "transfer done");
+  Ok(())
+}
+
+// --stay-open: one transfer is over, and the connection stays up for whatever the sender offers next;
+// `report_after_ack` has already made ready for it.
+fn finish_transfer(progress: &mut ReceiveProgress, outcome: Result<()>) {
+  progress.summary.record(&outcome);
+  match outcome {
+    Ok(()) => {
+      log_line("[recv] completed", "transfer done");
+      events::emit(Event::Done);
+    }
+    Err(err) => {
+      log_line("[recv] failed", &format!("{err:#}"));
+      events::emit(Event::Error {
+        code: error::code(&err),
+        message: format!("{err:#}"),
+        peer_id: None,
+      });
+    }
+  }
+  log_line("[recv] total", &progress.summary.describe());
+}
+
+async fn gathered_description(pc: &RTCPeerConnection) -> Result<RTCSessionDescription> {
+  let mut gathered = pc.gathering_complete_promise().await;
+  let _ = gathered.recv().await;
+  pc.local_description()
+    .await
+    .ok_or_else(|| anyhow!("local description is not set"))
+}
+
+fn encode_manual_signal(signal: &ManualSignal) -> Result<String> {
+  Ok(b64url_encode(serde_json::to_string(signal)?.as_bytes()))
+}
+
+// The pasteable line is the only thing on stdout in manual mode; with --json it is an event instead,
+// and with `-o -` it goes to stderr with the logs.
+fn print_manual_signal(kind: &str, signal: &ManualSignal) -> Result<()> {
+  let encoded = encode_manual_signal(signal)?;
+  if events::enabled() {
+    events::emit(Event::Signal { kind, signal: &encoded });
+  } else if progress::stdout_reserved() {
+    eprintln!("{encoded}");
+  } else {
+    println!("{encoded}");
+  }
+  Ok(())
+}
+
+async fn read_manual_signal() -> Result<ManualSignal> {
+  let mut lines = BufReader::new(tokio::io::stdin()).lines();
+  while let Some(line) = lines.next_line().await.context("read stdin")? {
+    let line = line.trim();
+    if line.is_empty() {
+      continue;
+    }
+    let json = b64url_decode(line).context("pasted text is not a pairlane signal")?;
+    return serde_json::from_slice(&json).context("pasted text is not a pairlane signal");
+  }
+  Err(anyhow!("stdin closed before a signal was pasted"))
+}
+
+async fn handle_receiver_message(
+  parsed: ServerMessage,
+  receiver_state: &Arc<Mutex<Option<ReceiverState>>>,
+  progress: &Arc<Mutex<ReceiveProgress>>,
+  signal_tx: &mpsc::UnboundedSender<ClientMessage>,
+  ice_servers: &[RTCIceServer],
+) -> Result<()> {
+  match parsed {
+    ServerMessage::Role { role, cid } => {
+      log_line("[ws] role", &format!("{role} ({cid})"));
+      events::emit(Event::Role { role: &role, cid: &cid });
+      if role != "answerer" {
+        return Err(ErrorKind::Signaling.error("This command must be the answerer; connect after the sender."));
+      }
+    }
+    ServerMessage::Peers { count } => {
+      log_line("[ws] peers", &count.to_string());
+    }
+    ServerMessage::Wait { position } => {
+      // Sent again when the sender leaves; its connection is gone with it. Unless only a websocket
+      // dropped: then the connection is still up and the transfer goes on.
+      if receiver_connected(receiver_state).await {
+        log_line("[rtc] still connected", "the sender's connection is up");
+        return Ok(());
+      }
+      end_connection(receiver_state, progress).await;
+      let label = position.map(|p| p.to_string()).unwrap_or_else(|| "waiting".to_string());
+      log_line("[ws] queue", &label);
+      events::emit(Event::Queue { position });
+    }
+    // Started again after signaling dropped; a new sender's offer still replaces the connection.
+    ServerMessage::Start { .. } if receiver_connected(receiver_state).await => {}
+    ServerMessage::Start { .. } => {
+      end_connection(receiver_state, progress).await;
+      start_connection(receiver_state, progress, signal_tx, ice_servers).await?;
+    }
+    // Only sent while no transfer is under way. With --stay-open a later sender may still come.
+    ServerMessage::Closing { reason } => {
+      log_line("[ws] closing", &reason);
+      if !progress.lock().await.options.stay_open {
+        return Err(ErrorKind::PeerAbort.error(format!("The sender is not taking more receivers: {reason}")));
+      }
+      end_connection(receiver_state, progress).await;
+    }
+    ServerMessage::Offer { from, sid, sdp } => {
+      let restart = match receiver_state.lock().await.as_ref() {
+        Some(state) if state.active_sid == Some(sid) && state.peer_id.as_ref() == Some(&from) => {
+          log_line("[rtc] offer", &format!("ignoring a repeated offer (sid {sid})"));
+          return Ok(());
+        }
+        // The sender started over with a new peer connection without leaving the room.
+        Some(state) => state.remote_desc_set,
+        None => true,
+      };
+      if restart {
+        end_connection(receiver_state, progress).await;
+        start_connection(receiver_state, progress, signal_tx, ice_servers).await?;
+      }
+      let mut guard = receiver_state.lock().await;
+      let state = guard.as_mut().ok_or_else(|| anyhow!("Receiver not initialized"))?;
+      state.peer_id = Some(from.clone());
+      state.active_sid = Some(sid);
+      state.pc.set_remote_description(sdp).await?;
+      state.remote_desc_set = true;
+      flush_receiver_candidates(state).await?;
+
+      let answer = state.pc.create_answer(None).await?;
+      state.pc.set_local_description(answer).await?;
+      if let Some(local) = state.pc.local_description().await {
+        let _ = signal_tx.send(ClientMessage::Answer { to: from, sid, sdp: local });
+      }
+    }
+    ServerMessage::Candidate { from: _, sid, candidate } => {
+      let mut guard = receiver_state.lock().await;
+      if let Some(state) = guard.as_mut() {
+        handle_receiver_candidate(state, sid, candidate).await?;
+      }
+    }
+    _ => {}
+  }
+  Ok(())
+}
+
+// Sets up a peer connection for the next offer, as a new session of `progress`.
+async fn start_connection(
+  receiver_state: &Arc<Mutex<Option<ReceiverState>>>,
+  progress: &Arc<Mutex<ReceiveProgress>>,
+  signal_tx: &mpsc::UnboundedSender<ClientMessage>,
+  ice_servers: &[RTCIceServer],
+) -> Result<()> {
+  let session = progress.lock().await.next_session();
+  let pc = create_peer_connection(ice_servers).await?;
+  let state_progress = progress.clone();
+  let connected = Arc::new(AtomicBool::new(false));
+  pc.on_peer_connection_state_change(Box::new(move |state: RTCPeerConnectionState| {
+    let progress = state_progress.clone();
+    let connected = connected.clone();
+    Box::pin(async move {
+      log_line("[rtc] connectionState", &format!("{:?}", state));
+      events::emit(Event::Connection {
+        peer_id: None,
+        state: state.to_string(),
+      });
+      match state {
+        RTCPeerConnectionState::Connected => connected.store(true, Ordering::Relaxed),
+        RTCPeerConnectionState::Failed => {
+          let mut guard = progress.lock().await;
+          if guard.session == session && guard.transfer_underway() {
+            let err = connection_failure(connected.load(Ordering::Relaxed), "sender");
+            report_failure(&mut guard, err);
+          }
+        }
+        _ => {}
+      }
+    })
+  }));
+  let tx = signal_tx.clone();
+  let receiver_state_for_ice = receiver_state.clone();
+  pc.on_ice_candidate(Box::new(move |candidate| {
+    let tx = tx.clone();
+    let receiver_state = receiver_state_for_ice.clone();
+    Box::pin(async move {
+      if let Some(candidate) = candidate {
+        let candidate = candidate.to_json().unwrap_or_default();
+        let guard = receiver_state.lock().await;
+        if let Some(state) = guard.as_ref() {
+          if let (Some(peer_id), Some(sid)) = (state.peer_id.clone(), state.active_sid) {
+            let _ = tx.send(ClientMessage::Candidate { to: peer_id, sid, candidate });
+          }
+        }
+      }
+    })
+  }));
+
+  let rx_progress = progress.clone();
+  pc.on_data_channel(Box::new(move |dc| {
+    let rx_progress = rx_progress.clone();
+    Box::pin(async move {
+      wire_receiver_channel(dc, rx_progress, session).await;
+    })
+  }));
+
+  *receiver_state.lock().await = Some(ReceiverState {
+    pc,
+    peer_id: None,
+    active_sid: None,
+    pending_candidates: Vec::new(),
+    remote_desc_set: false,
+  });
+  Ok(())
+}
+
+// Closes the current peer connection, if there is one. A transfer it leaves unfinished fails; the
+// next session starts from a clean slate either way.
+async fn end_connection(receiver_state: &Arc<Mutex<Option<ReceiverState>>>, progress: &Arc<Mutex<ReceiveProgress>>) {
+  let Some(state) = receiver_state.lock().await.take() else {
+    return;
+  };
+  {
+    let mut guard = progress.lock().await;
+    if guard.transfer_underway() {
+      report_failure(&mut guard, ErrorKind::PeerAbort.error("sender left before the transfer finished"));
+    }
+    guard.next_session();
+  }
+  let _ = state.pc.close().await;
+}
+
+async fn create_offerer_peer(
+  peer_id: String,
+  signal_tx: mpsc::UnboundedSender<ClientMessage>,
+  context: Arc<SendContext>,
+  outcome_tx: mpsc::UnboundedSender<PeerOutcome>,
+  burn_tx: Option<mpsc::UnboundedSender<String>>,
+) -> Result<Arc<OffererPeer>> {
+  let pc = create_peer_connection(&context.ice_servers).await?;
+  let dc = pc
+    .create_data_channel(
+      "file",
+      Some(RTCDataChannelInit {
+        ordered: Some(true),
+        ..Default::default()
+      }),
     )
     .await?;
 
+  let (queue_tx, queue_rx) = mpsc::unbounded_channel::<Vec<FileInfo>>();
   let peer = Arc::new(OffererPeer {
     peer_id: peer_id.clone(),
     pc: pc.clone(),
@@ -553,7 +2032,11 @@ async fn create_offerer_peer(
       pending_candidates: Vec::new(),
       remote_desc_set: false,
       sending: false,
+      queued: context.files.len(),
+      delivered: 0,
+      activity: PeerActivity::Connecting,
     })),
+    queue: queue_tx,
   });
 
   let peer_clone = peer.clone();
@@ -576,27 +2059,74 @@ async fn create_offerer_peer(
     })
   }));
 
+  let state_peer_id = peer_id.clone();
+  let state_outcome_tx = outcome_tx.clone();
+  let state_peer = peer.state.clone();
+  let connected = Arc::new(AtomicBool::new(false));
   pc.on_peer_connection_state_change(Box::new(move |state: RTCPeerConnectionState| {
+    let peer_id = state_peer_id.clone();
+    let outcome_tx = state_outcome_tx.clone();
+    let peer_state = state_peer.clone();
+    let connected = connected.clone();
     Box::pin(async move {
       log_line("[rtc] connectionState", &format!("{:?}", state));
+      events::emit(Event::Connection {
+        peer_id: Some(&peer_id),
+        state: state.to_string(),
+      });
+      match state {
+        RTCPeerConnectionState::Connected => connected.store(true, Ordering::Relaxed),
+        // The data channel never opens on a connection that failed to come up, so nothing else
+        // would report it.
+        RTCPeerConnectionState::Failed => {
+          let err = connection_failure(connected.load(Ordering::Relaxed), "receiver");
+          let mut peer_state = peer_state.lock().await;
+          if !matches!(peer_state.activity, PeerActivity::Failed(_)) {
+            peer_state.activity = PeerActivity::Failed(format!("{err:#}"));
+          }
+          peer_state.queued = 0;
+          drop(peer_state);
+          let _ = outcome_tx.send(PeerOutcome {
+            peer_id,
+            result: Err(err),
+          });
+        }
+        _ => {}
+      }
+    })
+  }));
+
+  let (control_tx, control_rx) = mpsc::unbounded_channel::<ControlMessage>();
+  dc.on_message(Box::new(move |msg: DataChannelMessage| {
+    let control_tx = control_tx.clone();
+    Box::pin(async move {
+      if !msg.is_string {
+        return;
+      }
+      if let Ok(parsed) = serde_json::from_slice::<ControlMessage>(&msg.data) {
+        let _ = control_tx.send(parsed);
+      }
     })
   }));
 
   let send_tx = signal_tx.clone();
   let send_peer_id = peer_id.clone();
-  let file_info = file_info.clone();
   let send_state = peer.state.clone();
   let dc_for_open = dc.clone();
-  let crypto = crypto.clone();
-  let success_tx = success_tx.clone();
+  let control = Arc::new(Mutex::new(PeerControl::new(control_rx)));
+  let queue = Arc::new(Mutex::new(Some(queue_rx)));
+  let pc_for_open = pc.clone();
   dc.on_open(Box::new(move || {
     let send_tx = send_tx.clone();
     let send_peer_id = send_peer_id.clone();
-    let file_info = file_info.clone();
+    let context = context.clone();
     let dc = dc_for_open.clone();
     let send_state = send_state.clone();
-    let crypto = crypto.clone();
-    let success_tx = success_tx.clone();
+    let outcome_tx = outcome_tx.clone();
+    let burn_tx = burn_tx.clone();
+    let control = control.clone();
+    let queue = queue.clone();
+    let pc = pc_for_open.clone();
     Box::pin(async move {
       let mut guard = send_state.lock().await;
       if guard.sending {
@@ -604,14 +2134,123 @@ async fn create_offerer_peer(
       }
       guard.sending = true;
       drop(guard);
+      let Some(mut queue) = queue.lock().await.take() else {
+        return;
+      };
 
-      if let Err(err) = send_file(&dc, &file_info, crypto).await {
-        log_line("[send] error", &format!("{err:#}"));
+      let mut control = control.lock().await;
+      let buffer_low = Arc::new(Notify::new());
+      dc.set_buffered_amount_low_threshold(BUFFER_LOW_THRESHOLD).await;
+      let notify = buffer_low.clone();
+      dc.on_buffered_amount_low(Box::new(move || {
+        let notify = notify.clone();
+        Box::pin(async move {
+          notify.notify_one();
+        })
+      }))
+      .await;
+      let crypto = match authenticate_peer(&dc, &context, &mut control).await.kind(ErrorKind::Auth) {
+        Ok(crypto) => crypto,
+        Err(err) => {
+          log_line("[send] rejected", &format!("{send_peer_id}: {err:#}"));
+          events::emit(Event::Error {
+            code: error::code(&err),
+            message: format!("{err:#}"),
+            peer_id: Some(&send_peer_id),
+          });
+          let rejected = serde_json::json!({ "type": "rejected", "reason": "key" });
+          let _ = send_data(&dc, None, &rejected).await;
+          if let Some(tx) = burn_tx.as_ref().filter(|_| context.code.is_some()) {
+            let _ = tx.send(format!("{send_peer_id}: {err:#}"));
+          }
+          let _ = send_tx.send(ClientMessage::PeerRejected {
+            peer_id: send_peer_id.clone(),
+            reason: "key".to_string(),
+          });
+          let mut state = send_state.lock().await;
+          state.activity = PeerActivity::Failed(format!("{err:#}"));
+          state.queued = 0;
+          drop(state);
+          let _ = outcome_tx.send(PeerOutcome {
+            peer_id: send_peer_id,
+            result: Err(err),
+          });
+          // Closing the connection from inside its own callback can stall webrtc-rs.
+          tokio::spawn(async move {
+            let _ = pc.close().await;
+          });
+          return;
+        }
+      };
+      // Started together with others while the last --max-receivers slot was still free.
+      if !context.admissions.admit() {
+        log_line("[send] turned away", &send_peer_id);
+        let reason = context.admissions.full().map(shutdown::Stop::reason).unwrap_or_default();
+        let _ = send_tx.send(ClientMessage::Closing {
+          reason,
+          peer_id: Some(send_peer_id.clone()),
+        });
+        let mut state = send_state.lock().await;
+        state.activity = PeerActivity::TurnedAway;
+        state.queued = 0;
+        drop(state);
+        tokio::spawn(async move {
+          let _ = pc.close().await;
+        });
         return;
       }
-      let _ = send_tx.send(ClientMessage::TransferDone { peer_id: send_peer_id });
-      if let Some(tx) = success_tx.as_ref() {
-        let _ = tx.send(());
+      let features = control
+        .recv(HELLO_TIMEOUT, |message| match message {
+          ControlMessage::Hello { features } => Ok(PeerFeatures::from_hello(&features)),
+          other => Err(other),
+        })
+        .await
+        .unwrap_or_default();
+      let link = PeerLink {
+        peer_id: send_peer_id.clone(),
+        buffer_low,
+        progress: ProgressRow::new("[send]", Some(send_peer_id.clone())),
+        features,
+      };
+      send_state.lock().await.activity = PeerActivity::Sending;
+      // A sender that only pushes has nothing for a receiver until the first file is queued.
+      let result = if context.files.is_empty() {
+        Ok(())
+      } else {
+        send_files(&dc, &context, &context.files, crypto.clone(), &mut control, &link).await
+      };
+      if result.is_ok() {
+        let _ = send_tx.send(ClientMessage::TransferDone {
+          peer_id: send_peer_id.clone(),
+        });
+      }
+      // A receiver that takes one transfer per connection gets the first pushed batch when there was
+      // nothing to send up front.
+      let more = result.is_ok() && (link.features.push || context.files.is_empty());
+      finish_batch(&send_state, &link, context.files.len(), &result, more).await;
+      if !context.files.is_empty() {
+        let _ = outcome_tx.send(PeerOutcome {
+          peer_id: send_peer_id.clone(),
+          result,
+        });
+      }
+      if !more {
+        return;
+      }
+      // --stay-open: what is pushed later goes out in order, one batch at a time. A receiver that
+      // rejects a batch, or does not list `push`, gets nothing more.
+      while let Some(files) = queue.recv().await {
+        send_state.lock().await.activity = PeerActivity::Sending;
+        let result = send_files(&dc, &context, &files, crypto.clone(), &mut control, &link).await;
+        let more = result.is_ok() && link.features.push;
+        finish_batch(&send_state, &link, files.len(), &result, more).await;
+        let _ = outcome_tx.send(PeerOutcome {
+          peer_id: send_peer_id.clone(),
+          result,
+        });
+        if !more {
+          break;
+        }
       }
     })
   }));
@@ -689,10 +2328,11 @@ async fn flush_offer_candidates(peer: Arc<OffererPeer>) -> Result<()> {
 }
 
 async fn handle_receiver_candidate(state: &mut ReceiverState, sid: u64, candidate: RTCIceCandidateInit) -> Result<()> {
-  if state.remote_desc_set {
-    state.pc.add_ice_candidate(candidate).await?;
-  } else {
-    state.pending_candidates.push(PendingCandidate { sid, candidate });
+  match state.active_sid {
+    Some(active) if state.remote_desc_set && sid == active => state.pc.add_ice_candidate(candidate).await?,
+    // Left over from an offer that has been replaced.
+    Some(active) if sid < active => {}
+    _ => state.pending_candidates.push(PendingCandidate { sid, candidate }),
   }
   Ok(())
 }
@@ -708,7 +2348,7 @@ async fn flush_receiver_candidates(state: &mut ReceiverState) -> Result<()> {
   for item in pending {
     if item.sid == sid {
       state.pc.add_ice_candidate(item.candidate).await?;
-    } else {
+    } else if item.sid > sid {
       remaining.push(item);
     }
   }
@@ -716,46 +2356,242 @@ async fn flush_receiver_candidates(state: &mut ReceiverState) -> Result<()> {
   Ok(())
 }
 
-async fn wire_receiver_channel(dc: Arc<RTCDataChannel>, progress: Arc<Mutex<ReceiveProgress>>) {
+async fn wire_receiver_channel(dc: Arc<RTCDataChannel>, progress: Arc<Mutex<ReceiveProgress>>, session: u64) {
+  let dc_for_open = dc.clone();
+  let open_progress = progress.clone();
+  dc.on_open(Box::new(move || {
+    let dc = dc_for_open.clone();
+    let progress = open_progress.clone();
+    Box::pin(async move {
+      let mut features = vec![
+        FEATURE_RESUME.to_string(),
+        FEATURE_STREAM_AEAD.to_string(),
+        FEATURE_ACK.to_string(),
+        FEATURE_UNSIZED.to_string(),
+      ];
+      {
+        let guard = progress.lock().await;
+        if guard.session != session {
+          return;
+        }
+        if !guard.options.approval.is_auto() {
+          features.push(FEATURE_APPROVE.to_string());
+        }
+        if guard.options.stay_open {
+          features.push(FEATURE_PUSH.to_string());
+        }
+      }
+      let hello = ControlMessage::Hello { features };
+      if let Err(err) = send_control(&dc, &hello).await {
+        log_line("[recv] error", &format!("{err:#}"));
+      }
+    })
+  }));
+
+  // Once the transfer has been reported this is a no-op; before that the sender went away mid-transfer.
+  let close_progress = progress.clone();
+  dc.on_close(Box::new(move || {
+    let progress = close_progress.clone();
+    Box::pin(async move {
+      let mut guard = progress.lock().await;
+      if guard.session == session && guard.transfer_underway() {
+        report_failure(&mut guard, ErrorKind::PeerAbort.error("sender closed the connection before the transfer finished"));
+      }
+    })
+  }));
+
+  let (finished, finished_rx) = mpsc::unbounded_channel();
+  tokio::spawn(finish_files(progress.clone(), dc.clone(), finished_rx));
+  let dc_for_message = dc.clone();
   dc.on_message(Box::new(move |msg: DataChannelMessage| {
     let progress = progress.clone();
+    let dc = dc_for_message.clone();
+    let finished = finished.clone();
     Box::pin(async move {
+      if progress.lock().await.session != session {
+        return;
+      }
       if msg.is_string {
         if let Ok(text) = String::from_utf8(msg.data.to_vec()) {
           if let Ok(parsed) = serde_json::from_str::<DataMessage>(&text) {
+            let parsed = match parsed {
+              DataMessage::Sealed { data } => {
+                let mut guard = progress.lock().await;
+                match open_sealed(guard.crypto.as_deref(), &data).kind(ErrorKind::Auth) {
+                  Ok(inner) => inner,
+                  Err(err) => {
+                    log_line("[recv] error", &format!("{err:#}"));
+                    report_failure(&mut guard, err);
+                    return;
+                  }
+                }
+              }
+              other => other,
+            };
             match parsed {
-              DataMessage::Meta { name, size, mime, encrypted } => {
+              DataMessage::Sealed { .. } => {
+                log_line("[recv] error", "nested sealed message ignored");
+              }
+              DataMessage::Pake { msg } => {
                 let mut guard = progress.lock().await;
-                if encrypted && guard.crypto.is_none() {
-                  log_line("[recv] error", "encrypted files need a decryption key");
+                let Some(password) = guard.code.clone() else {
+                  let err = ErrorKind::Auth.error("sender expects a code (send --code); receive with that code");
+                  log_line("[recv] error", &format!("{err:#}"));
+                  report_failure(&mut guard, err);
                   return;
+                };
+                let (state, outbound) = pake_start(&password, &guard.room_id);
+                let reply = ControlMessage::Pake { msg: b64url_encode(&outbound) };
+                if let Err(err) = send_control(&dc, &reply).await {
+                  log_line("[recv] error", &format!("{err:#}"));
+                  return;
+                }
+                let keys = pake_finish(state, &msg).and_then(|key| Ok((build_crypto(&key)?, key)));
+                match keys {
+                  Ok((crypto, key)) => {
+                    guard.crypto = Some(Arc::new(crypto));
+                    guard.room_key = Some(key);
+                  }
+                  Err(err) => {
+                    log_line("[recv] error", &format!("{err:#}"));
+                    report_failure(&mut guard, err);
+                  }
                 }
-                guard.encrypted = encrypted;
-                let safe_name = sanitize_file_name(&name);
-                let path = guard.output_dir.join(&safe_name);
-                match File::create(&path).await {
-                  Ok(file) => {
-                    guard.current_file = Some(path);
-                    guard.file = Some(file);
-                    guard.expected_size = size;
-                    guard.received = 0;
-                    log_line("[recv] meta", &format!("{safe_name} ({mime}, {size} bytes)"));
+              }
+              DataMessage::Challenge { nonce } => {
+                let mut guard = progress.lock().await;
+                let proof = match guard.room_key.as_deref() {
+                  Some(key) => b64url_decode(&nonce).and_then(|nonce| key_proof(key, &guard.room_id, &nonce)),
+                  None => Err(ErrorKind::Auth.error("sender requires the room key; open the full room URL")),
+                };
+                match proof {
+                  Ok(mac) => {
+                    guard.stream_required = true;
+                    let proof = ControlMessage::Proof {
+                      mac: b64url_encode(&mac.finalize().into_bytes()),
+                    };
+                    if let Err(err) = send_control(&dc, &proof).await {
+                      log_line("[recv] error", &format!("{err:#}"));
+                    }
                   }
                   Err(err) => {
                     log_line("[recv] error", &format!("{err:#}"));
+                    report_failure(&mut guard, err);
                   }
                 }
               }
-              DataMessage::Done => {
+              DataMessage::Rejected { reason } => {
+                let mut guard = progress.lock().await;
+                let err = ErrorKind::Auth.error(format!("sender rejected this receiver ({reason}); check the room key"));
+                log_line("[recv] error", &format!("{err:#}"));
+                report_failure(&mut guard, err);
+              }
+              DataMessage::Manifest { files } => {
+                let mut guard = progress.lock().await;
+                let total_size: u64 = files.iter().filter_map(|entry| entry.size).sum();
+                guard.expected_files = files.len().max(1);
+                guard.completed_files = 0;
+                log_line("[recv] manifest", &format!("{} files ({total_size} bytes)", files.len()));
+                let offer: Vec<approval::OfferedFile> = files
+                  .into_iter()
+                  .map(|entry| approval::OfferedFile {
+                    name: sanitize_relative_path(&entry.name).to_string_lossy().into_owned(),
+                    size: entry.size,
+                    mime: entry.mime,
+                  })
+                  .collect();
+                // A new manifest is judged afresh rather than against the last one.
+                guard.manifest = None;
+                if let Some(mut guard) = answer_offer(&progress, guard, &offer, &dc).await {
+                  guard.manifest = Some(offer.into_iter().map(|file| (file.name, file.size)).collect());
+                }
+              }
+              DataMessage::Meta { name, size, mime, encrypted, nonce_prefix, sha256 } => {
                 let mut guard = progress.lock().await;
-                guard.file = None;
-                guard.encrypted = false;
-                if let Some(tx) = guard.success_tx.take() {
-                  let _ = tx.send(());
+                let version = encrypted.version();
+                if version > 0 && guard.crypto.is_none() {
+                  let err = ErrorKind::Auth.error("encrypted files need a decryption key");
+                  log_line("[recv] error", &format!("{err:#}"));
+                  report_failure(&mut guard, err);
+                  return;
                 }
-                if let Some(path) = guard.current_file.take() {
-                  log_line("[recv] completed", &path.display().to_string());
+                // With a key nothing may arrive in the clear, and the browser is the only sender still on v1.
+                if guard.crypto.is_some() && (version == 0 || (version < STREAM_VERSION && guard.stream_required)) {
+                  let err = ErrorKind::Integrity.error(format!("sender fell back to encryption version {version}; refusing the downgrade"));
+                  log_line("[recv] error", &format!("{err:#}"));
+                  report_failure(&mut guard, err);
+                  return;
                 }
+                let stream = match version {
+                  0 | 1 => None,
+                  STREAM_VERSION => {
+                    let aad = stream_aad(&guard.room_id, &name, size, &mime, sha256.as_deref());
+                    match stream_for_meta(guard.crypto.clone(), nonce_prefix.as_deref(), aad) {
+                      Ok(stream) => Some(stream),
+                      Err(err) => {
+                        log_line("[recv] error", &format!("{err:#}"));
+                        report_failure(&mut guard, err);
+                        return;
+                      }
+                    }
+                  }
+                  other => {
+                    let err = anyhow!("unsupported encryption version {other}");
+                    log_line("[recv] error", &format!("{err:#}"));
+                    report_failure(&mut guard, err);
+                    return;
+                  }
+                };
+                // Senders that never send a manifest (the browser, a single file from an older CLI)
+                // are judged on every meta.
+                let offer = [approval::OfferedFile {
+                  name: sanitize_relative_path(&name).to_string_lossy().into_owned(),
+                  size,
+                  mime: Some(mime.clone()),
+                }];
+                let Some(mut guard) = answer_offer(&progress, guard, &offer, &dc).await else {
+                  return;
+                };
+                let align = if stream.is_some() { STREAM_CHUNK_SIZE as u64 } else { 1 };
+                guard.encrypted = version > 0;
+                guard.stream = stream;
+                guard.error = None;
+                let safe_path = sanitize_relative_path(&name);
+                guard.current_name = safe_path.to_string_lossy().into_owned();
+                guard.current_mime = mime.clone();
+                match open_output_file(&mut guard, &safe_path, size, sha256.as_deref(), align).await {
+                  Ok(offset) => {
+                    if let Some(stream) = guard.stream.as_mut() {
+                      if let Err(err) = stream.start_at(offset) {
+                        log_line("[recv] error", &format!("{err:#}"));
+                        guard.error = Some(err);
+                      }
+                    }
+                    let size_label = size.map_or("size unknown".to_string(), |size| format!("{size} bytes"));
+                    log_line("[recv] meta", &format!("{} ({mime}, {size_label})", safe_path.display()));
+                    events::emit(Event::Meta {
+                      name: &safe_path.to_string_lossy(),
+                      size,
+                      mime: &mime,
+                      offset,
+                    });
+                    guard.display.start(&safe_path.display().to_string(), size, offset);
+                    if offset > 0 {
+                      log_line("[recv] resume", &format!("{} from byte {offset}", safe_path.display()));
+                    }
+                    if let Err(err) = send_control(&dc, &ControlMessage::Resume { offset }).await {
+                      log_line("[recv] error", &format!("{err:#}"));
+                    }
+                  }
+                  Err(err) => {
+                    log_line("[recv] error", &format!("{err:#}"));
+                    report_failure(&mut guard, err);
+                  }
+                }
+              }
+              DataMessage::Done { sha256 } => {
+                let mut guard = progress.lock().await;
+                complete_current_file(&mut guard, sha256.as_deref(), &finished);
               }
             }
           }
@@ -763,108 +2599,1315 @@ async fn wire_receiver_channel(dc: Arc<RTCDataChannel>, progress: Arc<Mutex<Rece
         return;
       }
 
-      let (encrypted, crypto) = {
-        let guard = progress.lock().await;
-        (guard.encrypted, guard.crypto.clone())
-      };
-
-      let payload = if encrypted {
-        let crypto = match crypto.as_ref() {
-          Some(crypto) => crypto,
-          None => {
-            log_line("[recv] error", "encrypted chunk received without key");
-            return;
-          }
-        };
-        match decrypt_frame(crypto, msg.data.as_ref()) {
-          Ok(plain) => plain,
-          Err(err) => {
-            log_line("[recv] error", &format!("{err:#}"));
-            return;
-          }
-        }
-      } else {
-        msg.data.to_vec()
-      };
-
       let mut guard = progress.lock().await;
-      if let Some(file) = guard.file.as_mut() {
-        if file.write_all(&payload).await.is_ok() {
-          guard.received += payload.len() as u64;
-          if guard.expected_size > 0 && guard.received >= guard.expected_size {
-            guard.file = None;
-            guard.encrypted = false;
-            if let Some(tx) = guard.success_tx.take() {
-              let _ = tx.send(());
+      if guard.file.is_none() || guard.error.is_some() {
+        return;
+      }
+      let result = match open_payload(&mut guard, msg.data.as_ref()) {
+        Ok(payload) => {
+          match check_overrun(&guard, guard.received + payload.len() as u64) {
+            Ok(()) => {}
+            Err(Overrun::Announced(size)) => {
+              guard.error = Some(ErrorKind::Integrity.error(format!("received more than the announced {size} bytes")));
+              complete_current_file(&mut guard, None, &finished);
+              return;
+            }
+            Err(Overrun::Limit(reason)) => {
+              decline(&mut guard, &dc, reason).await;
+              return;
             }
-            if let Some(path) = guard.current_file.take() {
-              log_line("[recv] completed", &path.display().to_string());
+          }
+          let file = guard.file.as_mut().expect("checked above");
+          match file.write_all(&payload).await {
+            Ok(()) => {
+              guard.received += payload.len() as u64;
+              guard.display.set(guard.received);
+              guard.hasher.update(&payload);
+              Ok(())
             }
+            Err(err) => Err(anyhow!(err).context("write received file")),
           }
         }
+        Err(err) => Err(err),
+      };
+      if let Err(err) = result {
+        log_line("[recv] error", &format!("{err:#}"));
+        guard.error = Some(err);
       }
     })
   }));
 }
 
-async fn send_file(dc: &RTCDataChannel, info: &FileInfo, crypto: Option<Arc<Aes256Gcm>>) -> Result<()> {
-  let encrypted = crypto.is_some();
-  let meta = serde_json::json!({
+// Why a chunk that would take the current file to `received` bytes is not written.
+enum Overrun {
+  // Past the size in its meta: the file fails.
+  Announced(u64),
+  // Past --max-size for a stream of unknown length: the transfer is declined.
+  Limit(String),
+}
+
+// A sender that goes past what it announced is cut off instead of being left to fill the disk.
+fn check_overrun(progress: &ReceiveProgress, received: u64) -> std::result::Result<(), Overrun> {
+  if let Some(size) = progress.expected_size.filter(|size| received > *size) {
+    return Err(Overrun::Announced(size));
+  }
+  progress.options.limits.check_received(received).map_err(Overrun::Limit)
+}
+
+fn open_payload(progress: &mut ReceiveProgress, data: &[u8]) -> Result<Vec<u8>> {
+  if let Some(stream) = progress.stream.as_mut() {
+    return stream.open(data).kind(ErrorKind::Integrity);
+  }
+  if progress.encrypted {
+    let crypto = progress
+      .crypto
+      .as_ref()
+      .ok_or_else(|| ErrorKind::Auth.error("encrypted chunk received without key"))?;
+    return decrypt_frame(crypto, data).context("decrypt chunk").kind(ErrorKind::Integrity);
+  }
+  Ok(data.to_vec())
+}
+
+fn stream_for_meta(crypto: Option<Arc<Aes256Gcm>>, nonce_prefix: Option<&str>, aad: Vec<u8>) -> Result<StreamCipher> {
+  let crypto = crypto.ok_or_else(|| anyhow!("encrypted files need a decryption key"))?;
+  let prefix = b64url_decode(nonce_prefix.ok_or_else(|| anyhow!("meta is missing noncePrefix"))?)?;
+  let prefix: [u8; STREAM_PREFIX_LEN] = prefix
+    .try_into()
+    .map_err(|_| anyhow!("noncePrefix must be {STREAM_PREFIX_LEN} bytes"))?;
+  Ok(StreamCipher::new(crypto, prefix, aad))
+}
+
+// Checks the current file against the announced size and hash, and hands it on to be finished.
+fn complete_current_file(progress: &mut ReceiveProgress, sha256: Option<&str>, finished: &mpsc::UnboundedSender<FinishedFile>) {
+  let file = progress.file.take();
+  let stream = progress.stream.take();
+  let error = progress.error.take();
+  progress.encrypted = false;
+  let Some(path) = progress.current_file.take() else {
+    return;
+  };
+  progress.display.finish();
+  let hash = to_hex(&std::mem::take(&mut progress.hasher).finalize());
+  let result = match error {
+    Some(err) => Err(err),
+    None => Ok(()),
+  }
+  .and_then(|_| match stream {
+    Some(stream) if !stream.finished => Err(ErrorKind::Integrity.error("encrypted stream ended before its final frame")),
+    _ => Ok(()),
+  })
+  .and_then(|_| verify_received(progress.received, progress.expected_size, &hash, sha256));
+  let _ = finished.send(FinishedFile {
+    session: progress.session,
+    path,
+    part_path: progress.part_file.take(),
+    file,
+    child: progress.child.take(),
+    result,
+    conflict: progress.conflict,
+    skipped: std::mem::take(&mut progress.skipped),
+    to_stdout: progress.to_stdout,
+    on_complete: progress.options.on_complete.clone(),
+    name: progress.current_name.clone(),
+    mime: progress.current_mime.clone(),
+    received: progress.received,
+    hash,
+  });
+}
+
+// Finishes files one at a time, in the order they arrived, away from the data channel's message
+// handler: an --exec or --on-complete command takes as long as it takes, and the sender's next file
+// must not wait on it.
+async fn finish_files(progress: Arc<Mutex<ReceiveProgress>>, dc: Arc<RTCDataChannel>, mut finished: mpsc::UnboundedReceiver<FinishedFile>) {
+  while let Some(file) = finished.recv().await {
+    finish_file(&progress, &dc, file).await;
+  }
+}
+
+// Puts a checked file in place and acknowledges it to the sender; the whole transfer succeeds once
+// every manifest entry is in, and fails on the first file that does not check out.
+async fn finish_file(progress: &Mutex<ReceiveProgress>, dc: &Arc<RTCDataChannel>, finished: FinishedFile) {
+  let FinishedFile {
+    session,
+    mut path,
+    part_path,
+    mut file,
+    child,
+    result,
+    conflict,
+    mut skipped,
+    to_stdout,
+    on_complete,
+    name,
+    mime,
+    received,
+    hash,
+  } = finished;
+  let mut result = match file.as_mut() {
+    Some(file) => file.flush().await.context("flush received file"),
+    None => Ok(()),
+  }
+  .and(result);
+  let mut exit_code = None;
+  if let Some(mut child) = child {
+    // A command that quit early is why writing to it failed, so its status is the better report.
+    let exited = child.try_wait().ok().flatten();
+    // Closing its stdin tells the --exec command the input is complete, so a file that failed its
+    // checks stops the command first.
+    if exited.is_none() && result.is_err() {
+      let _ = child.start_kill();
+    }
+    drop(file.take());
+    let status = match exited {
+      Some(status) => Ok(status),
+      None => {
+        hook_running(dc).await;
+        child.wait().await
+      }
+    };
+    match status {
+      Ok(status) => {
+        exit_code = status.code();
+        if !status.success() && (result.is_ok() || exited.is_some()) {
+          result = Err(hooks::failure("--exec", status));
+        }
+      }
+      Err(err) if result.is_ok() => result = Err(anyhow!(err).context("wait for --exec command")),
+      Err(_) => {}
+    }
+  }
+  drop(file);
+  let mut committed = false;
+  if let (Ok(()), Some(part_path)) = (result.as_ref(), part_path.as_ref()) {
+    match commit_part(part_path, &path, conflict).await {
+      Ok(Some(saved)) => {
+        path = saved;
+        committed = true;
+      }
+      Ok(None) => skipped = true,
+      Err(err) => result = Err(err),
+    }
+  }
+  if let (Ok(()), Some(template), false) = (result.as_ref(), on_complete.as_deref(), skipped) {
+    let details = hooks::FileDetails {
+      path: (!to_stdout).then_some(path.as_path()),
+      name: &name,
+      size: Some(received),
+      mime: &mime,
+      sha256: Some(&hash),
+    };
+    hook_running(dc).await;
+    match hooks::run_on_complete(template, &details).await {
+      Ok(status) => {
+        exit_code = status.code();
+        if !status.success() {
+          result = Err(hooks::failure("--on-complete", status));
+        }
+      }
+      Err(err) => result = Err(err),
+    }
+  }
+  let ack = ControlMessage::Ack {
+    bytes: received,
+    verified: result.is_ok(),
+    error: result.as_ref().err().map(|err| format!("{err:#}")),
+    exit_code,
+  };
+  if let Err(err) = send_control(dc, &ack).await {
+    log_line("[recv] error", &format!("{err:#}"));
+  }
+  let mut progress = progress.lock().await;
+  // A connection that has been replaced meanwhile has nobody left to report to.
+  if progress.session != session {
+    return;
+  }
+  if let Err(err) = result {
+    let note = match part_path {
+      // The --on-complete command may already have picked the file up.
+      Some(_) if committed => " (file kept)",
+      Some(part_path) => {
+        let _ = tokio::fs::remove_file(&part_path).await;
+        " (file removed)"
+      }
+      // What went to stdout cannot be taken back; the exit status is all a pipeline gets.
+      None if to_stdout => " (output is incomplete)",
+      None => "",
+    };
+    log_line("[recv] error", &format!("{}: {err:#}{note}", path.display()));
+    report_after_ack(&mut progress, dc, Err(err));
+    return;
+  }
+  if skipped {
+    progress.summary.skipped += 1;
+    log_line("[recv] skipped", &format!("{} (kept the existing file)", path.display()));
+    events::emit(Event::Skipped {
+      path: &path.to_string_lossy(),
+    });
+  } else {
+    progress.summary.files += 1;
+    progress.summary.bytes += received;
+    log_line("[recv] completed", &format!("{} (sha256 {hash})", path.display()));
+    events::emit(Event::Completed {
+      peer_id: None,
+      path: &path.to_string_lossy(),
+      size: received,
+      sha256: &hash,
+    });
+  }
+  progress.completed_files += 1;
+  if progress.completed_files < progress.expected_files {
+    return;
+  }
+  report_after_ack(&mut progress, dc, Ok(()));
+}
+
+// Tells the sender a command is working on the file, so its wait for the ack is not cut short.
+async fn hook_running(dc: &RTCDataChannel) {
+  if let Err(err) = send_control(dc, &ControlMessage::Running).await {
+    log_line("[recv] error", &format!("{err:#}"));
+  }
+}
+
+// Reports the outcome once the last ack has left, so the receiver does not exit and close the
+// connection underneath it. The wait runs in its own task: SACKs are handled by the same loop that
+// calls `on_message`, so the queue cannot drain while we are still inside the handler.
+fn report_after_ack(progress: &mut ReceiveProgress, dc: &Arc<RTCDataChannel>, result: Result<()>) {
+  progress.expected_files = 1;
+  progress.completed_files = 0;
+  if let Some(tx) = progress.outcome_tx.take() {
+    // With --stay-open the sender may offer more right after the ack, before the outcome is through.
+    if progress.options.stay_open {
+      progress.next_transfer();
+    }
+    let dc = dc.clone();
+    tokio::spawn(async move {
+      // The sender hangs up as soon as it has the ack, often before our queue counter catches up.
+      let _ = wait_for_buffered(&dc, &Notify::new(), 0).await;
+      let _ = tx.send(result);
+    });
+  }
+}
+
+// Checks the manifest and every meta against the receiver's limits, holds each meta to the approved
+// manifest, and decides on anything else the sender offers. The lock is let go while deciding, since
+// a prompt can take as long as the user does; it comes back unless the transfer was declined or its
+// session ended meanwhile.
+async fn answer_offer<'a>(
+  progress: &'a Mutex<ReceiveProgress>,
+  mut guard: MutexGuard<'a, ReceiveProgress>,
+  offer: &[approval::OfferedFile],
+  dc: &Arc<RTCDataChannel>,
+) -> Option<MutexGuard<'a, ReceiveProgress>> {
+  if guard.approved == Some(false) {
+    return None;
+  }
+  if let Err(reason) = check_limits(&guard, offer) {
+    decline(&mut guard, dc, reason).await;
+    return None;
+  }
+  if let Some(manifest) = guard.manifest.as_mut() {
+    if let Err(reason) = take_manifest_entries(manifest, offer) {
+      decline(&mut guard, dc, reason).await;
+      return None;
+    }
+    return Some(guard);
+  }
+  let first = guard.approved.is_none();
+  if guard.options.approval.is_auto() {
+    guard.approved = Some(true);
+    return Some(guard);
+  }
+  let approval = guard.options.approval.clone();
+  let prompter = guard.options.prompter.clone();
+  let session = guard.session;
+  drop(guard);
+  let decision = approval::decide(&approval, &prompter, offer).await;
+  let mut guard = progress.lock().await;
+  if guard.session != session {
+    return None;
+  }
+  let reason = match decision {
+    Ok(approval::Decision::Accept) => None,
+    Ok(approval::Decision::Decline(reason)) => Some(reason),
+    Err(err) => Some(format!("{err:#}")),
+  };
+  if let Some(reason) = reason {
+    decline(&mut guard, dc, reason).await;
+    return None;
+  }
+  guard.approved = Some(true);
+  if first {
+    let answer = ControlMessage::Approval {
+      accepted: true,
+      reason: None,
+    };
+    if let Err(err) = send_control(dc, &answer).await {
+      log_line("[recv] error", &format!("{err:#}"));
+    }
+    log_line("[recv] accepted", "transfer accepted");
+  }
+  Some(guard)
+}
+
+// Uses up one manifest entry per offered file. A file of unknown length fits only an entry of
+// unknown length, and an entry of unknown length takes any size.
+fn take_manifest_entries(manifest: &mut Vec<(String, Option<u64>)>, offer: &[approval::OfferedFile]) -> std::result::Result<(), String> {
+  for file in offer {
+    let fits = |(name, size): &(String, Option<u64>)| {
+      *name == file.name
+        && match (size, file.size) {
+          (Some(max), Some(size)) => size <= *max,
+          (Some(_), None) => false,
+          (None, _) => true,
+        }
+    };
+    let Some(index) = manifest.iter().position(fits) else {
+      return Err(if manifest.iter().any(|(name, _)| *name == file.name) {
+        format!("{} is larger than the approved manifest said", file.name)
+      } else {
+        format!("{} is not in the approved manifest", file.name)
+      });
+    };
+    manifest.swap_remove(index);
+  }
+  Ok(())
+}
+
+fn check_limits(progress: &ReceiveProgress, offer: &[approval::OfferedFile]) -> std::result::Result<(), String> {
+  progress.options.limits.check(offer)?;
+  // Files handed to --exec or stdout take no room in the output directory.
+  if progress.options.exec.is_some() || progress.to_stdout {
+    return Ok(());
+  }
+  let needed: u64 = offer.iter().filter_map(|file| file.size).sum();
+  // The nearest directory that exists already; the rest of the path is created later.
+  let dir = progress
+    .output_dir
+    .ancestors()
+    .find(|dir| dir.is_dir())
+    .unwrap_or(Path::new("."));
+  // A filesystem that cannot report its free space is not a reason to decline.
+  match fs2::available_space(dir) {
+    Ok(available) if needed > available => Err(format!(
+      "not enough disk space: {} needed, {} free in {}",
+      progress::format_bytes(needed),
+      progress::format_bytes(available),
+      dir.display()
+    )),
+    _ => Ok(()),
+  }
+}
+
+// Tells the sender why, drops whatever file was being written and reports the transfer as declined.
+async fn decline(progress: &mut ReceiveProgress, dc: &Arc<RTCDataChannel>, reason: String) {
+  progress.approved = Some(false);
+  progress.file = None;
+  progress.stream = None;
+  if let Some(mut child) = progress.child.take() {
+    let _ = child.start_kill();
+  }
+  if let Some(part_path) = progress.part_file.take() {
+    let _ = tokio::fs::remove_file(&part_path).await;
+  }
+  if progress.current_file.take().is_some() {
+    progress.display.finish();
+  }
+  let answer = ControlMessage::Approval {
+    accepted: false,
+    reason: Some(reason.clone()),
+  };
+  if let Err(err) = send_control(dc, &answer).await {
+    log_line("[recv] error", &format!("{err:#}"));
+  }
+  log_line("[recv] declined", &reason);
+  report_after_ack(progress, dc, Err(ErrorKind::Declined.error(format!("declined the transfer: {reason}"))));
+}
+
+// A connection that never came up is an ICE problem; one that dropped later lost its peer.
+fn connection_failure(connected: bool, peer: &str) -> anyhow::Error {
+  if connected {
+    ErrorKind::PeerAbort.error(format!("lost the connection to the {peer}"))
+  } else {
+    ErrorKind::Ice.error(format!("could not establish a connection to the {peer}"))
+  }
+}
+
+fn report_failure(progress: &mut ReceiveProgress, err: anyhow::Error) {
+  progress.expected_files = 1;
+  progress.completed_files = 0;
+  if let Some(tx) = progress.outcome_tx.take() {
+    if progress.options.stay_open {
+      progress.next_transfer();
+    }
+    let _ = tx.send(Err(err));
+  }
+}
+
+fn verify_received(received: u64, expected_size: Option<u64>, hash: &str, expected_hash: Option<&str>) -> Result<()> {
+  if let Some(expected_size) = expected_size.filter(|expected_size| *expected_size != received) {
+    return Err(ErrorKind::Integrity.error(format!("size mismatch: received {received} of {expected_size} bytes")));
+  }
+  match expected_hash {
+    Some(expected) if !expected.eq_ignore_ascii_case(hash) => {
+      Err(ErrorKind::Integrity.error(format!("sha256 mismatch: expected {expected}, got {hash}")))
+    }
+    _ => Ok(()),
+  }
+}
+
+// Opens the `.part` file for `safe_path` and returns how many bytes are already on disk. A partial
+// is reused for a file of the same name and size; if its content changed, the hash in `done` no
+// longer matches and the partial is removed.
+async fn open_output_file(
+  progress: &mut ReceiveProgress,
+  safe_path: &Path,
+  size: Option<u64>,
+  sha256: Option<&str>,
+  align: u64,
+) -> Result<u64> {
+  if let Some(template) = progress.options.exec.as_deref() {
+    let details = hooks::FileDetails {
+      path: None,
+      name: &progress.current_name,
+      size,
+      mime: &progress.current_mime,
+      sha256,
+    };
+    let mut child = hooks::spawn_exec(template, &details)?;
+    let stdin = child.stdin.take().ok_or_else(|| anyhow!("--exec command has no stdin"))?;
+    progress.current_file = Some(safe_path.to_path_buf());
+    progress.part_file = None;
+    progress.file = Some(Box::new(stdin));
+    progress.child = Some(child);
+    progress.expected_size = size;
+    progress.received = 0;
+    progress.hasher = Sha256::new();
+    return Ok(0);
+  }
+  if progress.to_stdout {
+    // Several files would run together on stdout with nothing to tell them apart.
+    if progress.expected_files > 1 || progress.completed_files > 0 {
+      return Err(anyhow!("-o - takes a single file; this transfer has several (pipe a tar stream instead)"));
+    }
+    progress.current_file = Some(safe_path.to_path_buf());
+    progress.part_file = None;
+    progress.file = Some(Box::new(tokio::io::stdout()));
+    progress.expected_size = size;
+    progress.received = 0;
+    progress.hasher = Sha256::new();
+    return Ok(0);
+  }
+  // Settled before anything is written, so a skipped file never touches the disk.
+  let Some(path) = resolve_conflict(progress, progress.output_dir.join(safe_path)).await? else {
+    progress.current_file = Some(progress.output_dir.join(safe_path));
+    progress.part_file = None;
+    progress.file = Some(Box::new(tokio::io::sink()));
+    progress.skipped = true;
+    progress.expected_size = size;
+    progress.received = 0;
+    progress.hasher = Sha256::new();
+    return Ok(0);
+  };
+  let part_path = part_path_for(&path, size);
+  if let Some(parent) = path.parent() {
+    tokio::fs::create_dir_all(parent)
+      .await
+      .with_context(|| format!("create directory {}", parent.display()))?;
+  }
+  let existing = match size {
+    Some(_) => tokio::fs::metadata(&part_path).await.map(|m| m.len()).unwrap_or(0),
+    None => 0,
+  };
+  // Stream-encrypted files resume on a frame boundary so the frame counter lines up.
+  let offset = match size {
+    Some(size) if existing <= size => existing - existing % align,
+    _ => 0,
+  };
+  let mut file = OpenOptions::new()
+    .read(true)
+    .write(true)
+    .create(true)
+    .truncate(false)
+    .open(&part_path)
+    .await
+    .with_context(|| format!("create file {}", part_path.display()))?;
+  file.set_len(offset).await?;
+
+  // Bytes already on disk still count towards the final hash check.
+  let mut hasher = Sha256::new();
+  hash_prefix(&mut file, offset, &mut hasher)
+    .await
+    .with_context(|| format!("partial file shrank while resuming: {}", part_path.display()))?;
+  file.seek(SeekFrom::Start(offset)).await?;
+
+  progress.current_file = Some(path);
+  progress.part_file = Some(part_path);
+  progress.file = Some(Box::new(file));
+  progress.expected_size = size;
+  progress.received = offset;
+  progress.hasher = hasher;
+  Ok(offset)
+}
+
+// Applies `--on-conflict` when `path` is taken: the path to save to, or None to keep the existing file.
+async fn resolve_conflict(progress: &mut ReceiveProgress, path: PathBuf) -> Result<Option<PathBuf>> {
+  progress.conflict = match progress.options.on_conflict {
+    // A file that only appears while this one is on its way is not worth a question.
+    OnConflict::Ask => OnConflict::Rename,
+    policy => policy,
+  };
+  if !path_taken(&path).await {
+    return Ok(Some(path));
+  }
+  if progress.options.on_conflict == OnConflict::Ask {
+    progress.conflict = ask_conflict(progress, &path).await?;
+  }
+  match progress.conflict {
+    OnConflict::Overwrite => Ok(Some(path)),
+    OnConflict::Skip => {
+      log_line("[recv] skip", &format!("{} already exists", path.display()));
+      Ok(None)
+    }
+    _ => {
+      let mut n = 1;
+      while path_taken(&numbered_path(&path, n)).await {
+        n += 1;
+      }
+      let renamed = numbered_path(&path, n);
+      log_line("[recv] rename", &format!("{} already exists, saving as {}", path.display(), renamed.display()));
+      Ok(Some(renamed))
+    }
+  }
+}
+
+// An upper-case answer also settles every later conflict.
+async fn ask_conflict(progress: &mut ReceiveProgress, path: &Path) -> Result<OnConflict> {
+  let question = format!("{} already exists. [r]ename, [o]verwrite or [s]kip (R/O/S for all)?", path.display());
+  let (policy, for_all) = progress
+    .options
+    .prompter
+    .ask(&question, |answer| {
+      let policy = match answer.to_ascii_lowercase().as_str() {
+        "r" | "rename" => OnConflict::Rename,
+        "o" | "overwrite" => OnConflict::Overwrite,
+        "s" | "skip" => OnConflict::Skip,
+        _ => return None,
+      };
+      Some((policy, answer.starts_with(|c: char| c.is_ascii_uppercase())))
+    })
+    .await
+    .ok_or_else(|| anyhow!("stdin closed while asking about {}", path.display()))?;
+  if for_all {
+    progress.options.on_conflict = policy;
+  }
+  Ok(policy)
+}
+
+// Moves a verified part file into place. Unless overwriting, the move is a hard link, which fails
+// rather than replace a file that appeared while the transfer ran. None means that file was kept.
+async fn commit_part(part_path: &Path, path: &Path, policy: OnConflict) -> Result<Option<PathBuf>> {
+  if policy == OnConflict::Overwrite {
+    tokio::fs::rename(part_path, path)
+      .await
+      .with_context(|| format!("rename {}", part_path.display()))?;
+    return Ok(Some(path.to_path_buf()));
+  }
+  let mut target = path.to_path_buf();
+  let mut n = 0;
+  loop {
+    let taken = match tokio::fs::hard_link(part_path, &target).await {
+      Ok(()) => {
+        let _ = tokio::fs::remove_file(part_path).await;
+        return Ok(Some(target));
+      }
+      Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => true,
+      // Filesystems without hard links (FAT, some network shares) get a check before the rename.
+      Err(_) => path_taken(&target).await,
+    };
+    if !taken {
+      tokio::fs::rename(part_path, &target)
+        .await
+        .with_context(|| format!("rename {}", part_path.display()))?;
+      return Ok(Some(target));
+    }
+    if policy == OnConflict::Skip {
+      let _ = tokio::fs::remove_file(part_path).await;
+      return Ok(None);
+    }
+    n += 1;
+    target = numbered_path(path, n);
+  }
+}
+
+// A dangling symlink still counts as taken.
+async fn path_taken(path: &Path) -> bool {
+  tokio::fs::symlink_metadata(path).await.is_ok()
+}
+
+// `name.ext` -> `name (n).ext`
+fn numbered_path(path: &Path, n: u32) -> PathBuf {
+  let mut name = path.file_stem().unwrap_or_default().to_os_string();
+  name.push(format!(" ({n})"));
+  if let Some(extension) = path.extension() {
+    name.push(".");
+    name.push(extension);
+  }
+  path.with_file_name(name)
+}
+
+fn part_path_for(path: &Path, size: Option<u64>) -> PathBuf {
+  let mut name = path.file_name().map(|name| name.to_os_string()).unwrap_or_default();
+  if let Some(size) = size {
+    name.push(format!(".{size}"));
+  }
+  name.push(".part");
+  path.with_file_name(name)
+}
+
+// With a room key every sender message is sealed, so a peer without the key only learns that an
+// encrypted transfer is on offer, not the names, sizes or hashes.
+async fn send_data(dc: &RTCDataChannel, crypto: Option<&Aes256Gcm>, message: &serde_json::Value) -> Result<()> {
+  let text = serde_json::to_string(message)?;
+  let text = match crypto {
+    Some(crypto) => {
+      let sealed = serde_json::json!({
+        "type": "sealed",
+        "data": b64url_encode(&encrypt_frame(crypto, text.as_bytes())?),
+      });
+      serde_json::to_string(&sealed)?
+    }
+    None => text,
+  };
+  dc.send_text(text).await.kind(ErrorKind::PeerAbort)?;
+  Ok(())
+}
+
+fn open_sealed(crypto: Option<&Aes256Gcm>, data: &str) -> Result<DataMessage> {
+  let crypto = crypto.ok_or_else(|| anyhow!("an encrypted transfer is being offered; a decryption key is required"))?;
+  let plain = decrypt_frame(crypto, &b64url_decode(data)?).context("open sealed message (wrong key?)")?;
+  serde_json::from_slice(&plain).context("parse sealed message")
+}
+
+async fn send_control(dc: &RTCDataChannel, message: &ControlMessage) -> Result<()> {
+  dc.send_text(serde_json::to_string(message)?).await.kind(ErrorKind::PeerAbort)?;
+  Ok(())
+}
+
+impl PeerControl {
+  fn new(rx: mpsc::UnboundedReceiver<ControlMessage>) -> Self {
+    PeerControl {
+      rx,
+      stash: VecDeque::new(),
+    }
+  }
+
+  // Moves whatever has arrived into the stash without waiting.
+  fn poll(&mut self) {
+    while let Ok(message) = self.rx.try_recv() {
+      self.stash.push_back(message);
+    }
+  }
+
+  // Waits for the first message `select` accepts; it hands back the ones it does not want.
+  async fn recv<T>(
+    &mut self,
+    wait: Duration,
+    mut select: impl FnMut(ControlMessage) -> std::result::Result<T, ControlMessage>,
+  ) -> Option<T> {
+    for _ in 0..self.stash.len() {
+      let message = self.stash.pop_front()?;
+      match select(message) {
+        Ok(value) => return Some(value),
+        Err(message) => self.stash.push_back(message),
+      }
+    }
+    let deadline = tokio::time::Instant::now() + wait;
+    loop {
+      let message = timeout(deadline.saturating_duration_since(tokio::time::Instant::now()), self.rx.recv())
+        .await
+        .ok()??;
+      match select(message) {
+        Ok(value) => return Some(value),
+        Err(message) => self.stash.push_back(message),
+      }
+    }
+  }
+}
+
+// Returns the cipher for this peer: the room's own, or one keyed by PAKE when sending with a code.
+async fn authenticate_peer(
+  dc: &RTCDataChannel,
+  context: &SendContext,
+  control: &mut PeerControl,
+) -> Result<Option<Arc<Aes256Gcm>>> {
+  if let Some(password) = context.code.as_deref() {
+    let (state, outbound) = pake_start(password, &context.room_id);
+    let pake = serde_json::json!({ "type": "pake", "msg": b64url_encode(&outbound) });
+    send_data(dc, None, &pake).await?;
+    let inbound = control
+      .recv(AUTH_TIMEOUT, |message| match message {
+        ControlMessage::Pake { msg } => Ok(msg),
+        other => Err(other),
+      })
+      .await
+      .ok_or_else(|| ErrorKind::Timeout.error("peer did not answer the code exchange"))?;
+    let key = pake_finish(state, &inbound)?;
+    // A wrong code still yields a key, just a different one; the proof below is what catches it.
+    verify_peer_key(dc, &key, &context.room_id, control).await?;
+    return Ok(Some(Arc::new(build_crypto(&key)?)));
+  }
+  if let Some(key) = context.room_key.as_deref() {
+    verify_peer_key(dc, key, &context.room_id, control).await?;
+  }
+  Ok(context.crypto.clone())
+}
+
+fn pake_start(password: &str, room_id: &str) -> (Spake2<Ed25519Group>, Vec<u8>) {
+  let identity = format!("pairlane-pake-v1:{room_id}");
+  Spake2::<Ed25519Group>::start_symmetric(&Password::new(password.as_bytes()), &Identity::new(identity.as_bytes()))
+}
+
+fn pake_finish(state: Spake2<Ed25519Group>, inbound: &str) -> Result<Vec<u8>> {
+  state
+    .finish(&b64url_decode(inbound)?)
+    .map_err(|err| ErrorKind::Auth.error(format!("code exchange failed: {err}")))
+}
+
+// Challenge-response over the data channel: nothing is streamed to a peer that cannot show it
+// holds the room key, so strangers with only the room ID get no ciphertext.
+async fn verify_peer_key(dc: &RTCDataChannel, key: &[u8], room_id: &str, control: &mut PeerControl) -> Result<()> {
+  let mut nonce = [0u8; AUTH_NONCE_LEN];
+  getrandom(&mut nonce).map_err(|err| anyhow!(err))?;
+  let challenge = serde_json::json!({ "type": "challenge", "nonce": b64url_encode(&nonce) });
+  send_data(dc, None, &challenge).await?;
+  let mac = control
+    .recv(AUTH_TIMEOUT, |message| match message {
+      ControlMessage::Proof { mac } => Ok(mac),
+      other => Err(other),
+    })
+    .await
+    .ok_or_else(|| ErrorKind::Timeout.error("no key proof received"))?;
+  key_proof(key, room_id, &nonce)?
+    .verify_slice(&b64url_decode(&mac)?)
+    .map_err(|_| ErrorKind::Auth.error("key proof does not match"))
+}
+
+fn key_proof(key: &[u8], room_id: &str, nonce: &[u8]) -> Result<Hmac<Sha256>> {
+  let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&subkey(key, AUTH_KEY_INFO)?).map_err(|err| anyhow!(err))?;
+  mac.update(b"pairlane-auth-v1\0");
+  mac.update(room_id.as_bytes());
+  mac.update(b"\0");
+  mac.update(nonce);
+  Ok(mac)
+}
+
+// Books a finished batch against the peer's status and reports a failure. `more` is whether the
+// receiver takes further batches on this connection.
+async fn finish_batch(state: &Mutex<OffererPeerState>, link: &PeerLink, count: usize, result: &Result<()>, more: bool) {
+  if let Err(err) = result {
+    log_line("[send] error", &format!("{}: {err:#}", link.peer_id));
+    events::emit(Event::Error {
+      code: error::code(err),
+      message: format!("{err:#}"),
+      peer_id: Some(&link.peer_id),
+    });
+  }
+  let mut state = state.lock().await;
+  state.queued = state.queued.saturating_sub(count);
+  state.activity = match result {
+    Ok(()) if more => PeerActivity::Idle,
+    Ok(()) => PeerActivity::Finished,
+    Err(err) => PeerActivity::Failed(format!("{err:#}")),
+  };
+  if let Ok(()) = result {
+    state.delivered += count;
+  }
+  if !more {
+    // Whatever was pushed to it in the meantime will not be sent.
+    state.queued = 0;
+  }
+}
+
+async fn send_files(
+  dc: &RTCDataChannel,
+  context: &SendContext,
+  files: &[FileInfo],
+  crypto: Option<Arc<Aes256Gcm>>,
+  control: &mut PeerControl,
+  link: &PeerLink,
+) -> Result<()> {
+  let features = &link.features;
+  if files.iter().any(|file| file.size.is_none()) && !features.unknown_length {
+    return Err(anyhow!("receiver cannot take a stream of unknown length (browser or older pairlane)"));
+  }
+
+  // A receiver that wants to approve the transfer sees the manifest even for a single file.
+  if files.len() > 1 || features.approve {
+    let entries: Vec<ManifestEntry> = files
+      .iter()
+      .map(|file| ManifestEntry {
+        name: file.name.clone(),
+        size: file.size,
+        mime: Some(file.mime.clone()),
+      })
+      .collect();
+    let manifest = serde_json::json!({
+      "type": "manifest",
+      "files": entries,
+    });
+    send_data(dc, crypto.as_deref(), &manifest).await?;
+  }
+  if features.approve {
+    log_line("[send] waiting", &format!("for {} to accept the transfer", link.peer_id));
+    let (accepted, reason) = control
+      .recv(APPROVAL_TIMEOUT, |message| match message {
+        ControlMessage::Approval { accepted, reason } => Ok((accepted, reason)),
+        other => Err(other),
+      })
+      .await
+      .ok_or_else(|| ErrorKind::Timeout.error("receiver did not answer the transfer request"))?;
+    if !accepted {
+      return Err(declined(reason));
+    }
+  }
+  let mut sent = Vec::with_capacity(files.len());
+  for info in files {
+    match send_file(dc, context, crypto.clone(), files, info, control, link).await {
+      Ok(result) => sent.push(result),
+      // A receiver that rejects a file gives up on the rest, so its ack explains the hang-up.
+      Err(err) => return Err(pending_rejection(files, control).unwrap_or(err)),
+    }
+  }
+  if !features.ack {
+    // Older receivers and the browser client do not acknowledge; a drained buffer is all we get.
+    return wait_for_buffered(dc, &link.buffer_low, 0).await;
+  }
+  // Acks stand in for the drain: a receiver that rejects a file may hang up before our queue
+  // counter catches up with what it already has.
+  for (info, (size, sha256)) in files.iter().zip(&sent) {
+    let ack = AckReply::recv(control)
+      .await
+      .ok_or_else(|| ErrorKind::Timeout.error(format!("no ack for {}", info.name)))?;
+    if let Some(err) = ack.rejection(&info.name) {
+      return Err(err);
+    }
+    let bytes = ack.bytes;
+    if bytes != *size {
+      return Err(ErrorKind::Integrity.error(format!(
+        "receiver acknowledged {bytes} of {size} bytes for {}",
+        info.name
+      )));
+    }
+    events::emit(Event::Completed {
+      peer_id: Some(&link.peer_id),
+      path: &info.name,
+      size: *size,
+      sha256,
+    });
+  }
+  Ok(())
+}
+
+fn declined(reason: Option<String>) -> anyhow::Error {
+  let reason = reason.map(|reason| format!(": {reason}")).unwrap_or_default();
+  ErrorKind::Declined.error(format!("receiver declined the transfer{reason}"))
+}
+
+// Checks the acks that have already arrived for one that rejected its file, and whether the receiver
+// declined the transfer altogether. The acks stay queued for whoever reads them next.
+fn pending_rejection(files: &[FileInfo], control: &mut PeerControl) -> Option<anyhow::Error> {
+  control.poll();
+  let refusal = control.stash.iter().find_map(|message| match message {
+    ControlMessage::Approval { accepted: false, reason } => Some(reason.clone()),
+    _ => None,
+  });
+  if let Some(reason) = refusal {
+    return Some(declined(reason));
+  }
+  let acks = control.stash.iter().filter_map(|message| match message {
+    ControlMessage::Ack { bytes, verified, error, exit_code } => Some(AckReply {
+      bytes: *bytes,
+      verified: *verified,
+      error: error.clone(),
+      exit_code: *exit_code,
+    }),
+    _ => None,
+  });
+  files.iter().zip(acks).find_map(|(info, ack)| ack.rejection(&info.name))
+}
+
+async fn rejection_arrives(files: &[FileInfo], control: &mut PeerControl) -> anyhow::Error {
+  loop {
+    if let Some(err) = pending_rejection(files, control) {
+      return err;
+    }
+    tokio::time::sleep(BUFFER_POLL_INTERVAL).await;
+  }
+}
+
+impl AckReply {
+  async fn recv(control: &mut PeerControl) -> Option<Self> {
+    let mut wait = CONTROL_REPLY_TIMEOUT;
+    loop {
+      let reply = control
+        .recv(wait, |message| match message {
+          ControlMessage::Running => Ok(None),
+          other => AckReply::select(other).map(Some),
+        })
+        .await?;
+      match reply {
+        Some(ack) => return Some(ack),
+        None => wait = HOOK_REPLY_TIMEOUT,
+      }
+    }
+  }
+
+  fn select(message: ControlMessage) -> std::result::Result<Self, ControlMessage> {
+    match message {
+      ControlMessage::Ack { bytes, verified, error, exit_code } => Ok(AckReply {
+        bytes,
+        verified,
+        error,
+        exit_code,
+      }),
+      other => Err(other),
+    }
+  }
+
+  fn rejection(&self, name: &str) -> Option<anyhow::Error> {
+    if self.verified {
+      return None;
+    }
+    let reason = self.error.as_deref().unwrap_or("verification failed");
+    // A command that ran on the receiver reports its status; otherwise the file did not check out.
+    let kind = if self.exit_code.is_some_and(|code| code != 0) { ErrorKind::Hook } else { ErrorKind::Integrity };
+    Some(kind.error(format!("receiver rejected {name}: {reason}")))
+  }
+}
+
+// Returns the number of bytes sent and their SHA-256, which is worked out as the chunks go out.
+async fn send_file(
+  dc: &RTCDataChannel,
+  context: &SendContext,
+  crypto: Option<Arc<Aes256Gcm>>,
+  files: &[FileInfo],
+  info: &FileInfo,
+  control: &mut PeerControl,
+  link: &PeerLink,
+) -> Result<(u64, String)> {
+  let features = &link.features;
+  let mut stream = match crypto.clone() {
+    Some(crypto) if features.stream_aead => {
+      let mut prefix = [0u8; STREAM_PREFIX_LEN];
+      getrandom(&mut prefix).map_err(|err| anyhow!(err))?;
+      let aad = stream_aad(&context.room_id, &info.name, info.size, &info.mime, None);
+      Some(StreamCipher::new(crypto, prefix, aad))
+    }
+    _ => None,
+  };
+  let mut meta = serde_json::json!({
     "type": "meta",
     "name": info.name,
     "size": info.size,
     "mime": info.mime,
-    "encrypted": encrypted,
+    "encrypted": crypto.is_some(),
   });
-  let meta_text = serde_json::to_string(&meta)?;
-  dc.send_text(meta_text).await?;
+  if let Some(stream) = stream.as_ref() {
+    meta["encrypted"] = STREAM_VERSION.into();
+    meta["noncePrefix"] = b64url_encode(&stream.prefix).into();
+  }
+  send_data(dc, crypto.as_deref(), &meta).await?;
 
-  let chunk_size = if encrypted {
-    MAX_FRAME_SIZE - AES_NONCE_LEN - AES_TAG_LEN
+  // A receiver that supports resuming answers every meta with the offset to continue from, or
+  // declines the file when it breaks the receiver's limits.
+  let offset = if features.resume {
+    control
+      .recv(CONTROL_REPLY_TIMEOUT, |message| match message {
+        ControlMessage::Resume { offset } => Ok(Ok(offset)),
+        ControlMessage::Approval { accepted: false, reason } => Ok(Err(reason)),
+        other => Err(other),
+      })
+      .await
+      .ok_or_else(|| ErrorKind::Timeout.error(format!("receiver did not answer meta for {}", info.name)))?
+      .map_err(declined)?
   } else {
-    MAX_FRAME_SIZE
+    0
   };
-  let mut file = File::open(&info.path).await?;
-  let mut buffer = vec![0u8; chunk_size];
-  loop {
-    let read = file.read(&mut buffer).await?;
+  let offset = match stream.as_ref() {
+    Some(_) => offset - offset % STREAM_CHUNK_SIZE as u64,
+    None => offset,
+  }
+  .min(info.size.unwrap_or_default());
+
+  let chunk_size = match (&stream, &crypto) {
+    (Some(_), _) => STREAM_CHUNK_SIZE,
+    (None, Some(_)) => MAX_FRAME_SIZE - AES_NONCE_LEN - AES_TAG_LEN,
+    (None, None) => MAX_FRAME_SIZE,
+  };
+  let mut source = open_source(context, info).await?;
+  // The digest in `done` covers the whole file, so the part the receiver already has is read and
+  // hashed here instead of being sent again.
+  let mut hasher = Sha256::new();
+  if offset > 0 {
+    log_line("[send] resume", &format!("{} from byte {offset}", info.name));
+    hash_prefix(&mut source, offset, &mut hasher)
+      .await
+      .with_context(|| format!("read {}", info.path.display()))?;
+  }
+  if let Some(stream) = stream.as_mut() {
+    stream.start_at(offset)?;
+  }
+  link.progress.start(&info.name, info.size, offset);
+  let mut sent = offset;
+  // A receiver that gives up on a file stops reading without closing the channel on our side, and
+  // sending can then block until the connection times out. Its rejection ends the file instead.
+  let frames = async {
+    // Read one chunk ahead so the final frame can be flagged as last.
+    let mut current = read_chunk(&mut source, chunk_size).await?;
+    loop {
+      let next = if current.len() == chunk_size {
+        read_chunk(&mut source, chunk_size).await?
+      } else {
+        Vec::new()
+      };
+      let last = next.is_empty();
+      sent += current.len() as u64;
+      hasher.update(&current);
+      let payload = match (stream.as_mut(), crypto.as_ref()) {
+        (Some(stream), _) => Some(stream.seal(&current, last)?),
+        (None, _) if current.is_empty() => None,
+        (None, Some(crypto)) => Some(encrypt_frame(crypto, &current)?),
+        (None, None) => Some(current),
+      };
+      if let Some(payload) = payload {
+        if dc.buffered_amount().await > BUFFER_HIGH_WATER {
+          wait_for_buffered(dc, &link.buffer_low, BUFFER_LOW_THRESHOLD).await?;
+        }
+        dc.send(&Bytes::from(payload)).await.kind(ErrorKind::PeerAbort)?;
+      }
+      link.progress.set(sent);
+      if last {
+        break;
+      }
+      current = next;
+    }
+    Ok::<_, anyhow::Error>(())
+  };
+  tokio::select! {
+    result = frames => result?,
+    err = rejection_arrives(files, control) => return Err(err),
+  }
+
+  let sha256 = to_hex(&hasher.finalize());
+  let done = serde_json::json!({
+    "type": "done",
+    "sha256": sha256,
+  });
+  send_data(dc, crypto.as_deref(), &done).await?;
+  link.progress.finish();
+  Ok((sent, sha256))
+}
+
+// Regular files are opened once per receiver; stdin can only be read by the first one.
+async fn open_source(context: &SendContext, info: &FileInfo) -> Result<Box<dyn AsyncRead + Unpin + Send>> {
+  if info.size.is_none() {
+    if context.stdin_taken.swap(true, Ordering::Relaxed) {
+      return Err(anyhow!("stdin was already sent to another receiver"));
+    }
+    return Ok(Box::new(tokio::io::stdin()));
+  }
+  let file = File::open(&info.path)
+    .await
+    .with_context(|| format!("open {}", info.path.display()))?;
+  Ok(Box::new(file))
+}
+
+// Feeds the first `len` bytes of `source` into `hasher`, failing if it ends sooner.
+async fn hash_prefix(source: &mut (impl AsyncRead + Unpin), len: u64, hasher: &mut Sha256) -> Result<()> {
+  let mut remaining = len;
+  let mut buffer = vec![0u8; HASH_BUFFER_SIZE];
+  while remaining > 0 {
+    let want = remaining.min(buffer.len() as u64) as usize;
+    let read = source.read(&mut buffer[..want]).await?;
+    if read == 0 {
+      return Err(anyhow!("ended {remaining} bytes short"));
+    }
+    hasher.update(&buffer[..read]);
+    remaining -= read as u64;
+  }
+  Ok(())
+}
+
+// Fills a whole chunk unless the file ends first; stream framing relies on fixed-size frames.
+async fn read_chunk(source: &mut (impl AsyncRead + Unpin), chunk_size: usize) -> Result<Vec<u8>> {
+  let mut chunk = vec![0u8; chunk_size];
+  let mut filled = 0;
+  while filled < chunk_size {
+    let read = source.read(&mut chunk[filled..]).await?;
     if read == 0 {
       break;
     }
-    let payload = if let Some(crypto) = crypto.as_ref() {
-      Bytes::from(encrypt_frame(crypto, &buffer[..read])?)
+    filled += read;
+  }
+  chunk.truncate(filled);
+  Ok(chunk)
+}
+
+// A trailing positional that is not an existing path is the room (`send FILE ROOM`) if it looks like
+// one; anything else stays a path, so a mistyped file name fails as a missing file.
+fn split_send_inputs(mut values: Vec<PathBuf>) -> Result<(Vec<PathBuf>, Option<String>)> {
+  if values.len() < 2 || values.last().is_some_and(|value| value.exists()) {
+    return Ok((values, None));
+  }
+  if !values.last().and_then(|value| value.to_str()).is_some_and(looks_like_room) {
+    return Ok((values, None));
+  }
+  let room = values
+    .pop()
+    .and_then(|value| value.into_os_string().into_string().ok())
+    .ok_or_else(|| anyhow!("Room ID or URL must be valid UTF-8"))?;
+  Ok((values, Some(room)))
+}
+
+// A transfer code, a room URL, or a room ID the way the server makes them. Rooms with other names
+// are given with --room-id.
+fn looks_like_room(value: &str) -> bool {
+  if TransferCode::parse(value).is_some() {
+    return true;
+  }
+  if let Ok(url) = Url::parse(value) {
+    return matches!(url.scheme(), "http" | "https");
+  }
+  let room_id = value.split_once('#').map_or(value, |(room_id, _)| room_id);
+  room_id.len() == server::ROOM_ID_LEN && room_id.bytes().all(|byte| server::ROOM_ID_ALPHABET.contains(&byte))
+}
+
+async fn collect_files(paths: &[PathBuf], overrides: &FileOverrides) -> Result<Vec<FileInfo>> {
+  let mut files = Vec::new();
+  for path in paths {
+    if path.as_os_str() == STDIO_PATH {
+      files.push(FileInfo {
+        path: path.clone(),
+        name: STDIN_NAME.to_string(),
+        size: None,
+        mime: "application/octet-stream".to_string(),
+      });
+      continue;
+    }
+    let metadata = tokio::fs::metadata(path)
+      .await
+      .with_context(|| format!("read {}", path.display()))?;
+    if metadata.is_dir() {
+      collect_dir(path, &mut files).await?;
     } else {
-      Bytes::copy_from_slice(&buffer[..read])
+      files.push(load_file_info(path).await?);
+    }
+  }
+  if files.is_empty() {
+    return Err(anyhow!("Nothing to send: no regular files found"));
+  }
+  if overrides.name.is_some() || overrides.mime.is_some() {
+    let [file] = files.as_mut_slice() else {
+      return Err(anyhow!("--name and --mime need a single file"));
     };
-    dc.send(&payload).await?;
+    if let Some(name) = overrides.name.as_deref() {
+      file.name = name.to_string();
+      if overrides.mime.is_none() {
+        file.mime = guess_mime(Path::new(name));
+      }
+    }
+    if let Some(mime) = overrides.mime.as_deref() {
+      file.mime = mime.to_string();
+    }
+  }
+  let mut seen = HashSet::new();
+  for file in &files {
+    if !seen.insert(file.name.as_str()) {
+      return Err(anyhow!("Duplicate path in transfer: {}", file.name));
+    }
   }
+  Ok(files)
+}
+
+// Walks a directory without following directory symlinks; entries are named `<dir>/<relative path>`.
+async fn collect_dir(root: &Path, files: &mut Vec<FileInfo>) -> Result<()> {
+  let root_name = match root.file_name() {
+    Some(name) => name.to_os_string(),
+    None => tokio::fs::canonicalize(root)
+      .await?
+      .file_name()
+      .map(|name| name.to_os_string())
+      .unwrap_or_else(|| "files".into()),
+  };
+  let root_name = root_name
+    .into_string()
+    .map_err(|_| anyhow!("Invalid directory name: {}", root.display()))?;
 
-  dc.send_text("{\"type\":\"done\"}").await?;
-  wait_for_drain(dc).await;
+  let mut found = Vec::new();
+  let mut stack = vec![(root.to_path_buf(), root_name)];
+  while let Some((dir, prefix)) = stack.pop() {
+    let mut entries = tokio::fs::read_dir(&dir)
+      .await
+      .with_context(|| format!("read directory {}", dir.display()))?;
+    while let Some(entry) = entries.next_entry().await? {
+      let file_name = entry
+        .file_name()
+        .into_string()
+        .map_err(|_| anyhow!("Invalid file name in {}", dir.display()))?;
+      let name = format!("{prefix}/{file_name}");
+      let file_type = entry.file_type().await?;
+      if file_type.is_dir() {
+        stack.push((entry.path(), name));
+      } else if file_type.is_file() || tokio::fs::metadata(entry.path()).await.is_ok_and(|m| m.is_file()) {
+        let mut info = load_file_info(&entry.path()).await?;
+        info.name = name;
+        found.push(info);
+      }
+    }
+  }
+  found.sort_by(|a, b| a.name.cmp(&b.name));
+  files.extend(found);
   Ok(())
 }
 
 async fn load_file_info(path: &Path) -> Result<FileInfo> {
   let metadata = tokio::fs::metadata(path).await?;
+  if !metadata.is_file() {
+    return Err(anyhow!("Not a regular file: {}", path.display()));
+  }
   let size = metadata.len();
   let name = path
     .file_name()
     .and_then(|n| n.to_str())
     .ok_or_else(|| anyhow!("Invalid file name"))?
     .to_string();
-  let mime = mime_guess::from_path(path)
-    .first_or_octet_stream()
-    .essence_str()
-    .to_string();
+  let mime = guess_mime(path);
   Ok(FileInfo {
     path: path.to_path_buf(),
     name,
-    size,
+    size: Some(size),
     mime,
   })
 }
 
-async fn create_peer_connection() -> Result<Arc<RTCPeerConnection>> {
+fn guess_mime(path: &Path) -> String {
+  mime_guess::from_path(path)
+    .first_or_octet_stream()
+    .essence_str()
+    .to_string()
+}
+
+fn describe_files(files: &[FileInfo]) -> String {
+  match files {
+    [file] if file.size.is_none() => format!("{} (stream from stdin)", file.name),
+    _ => {
+      let total_size: u64 = files.iter().filter_map(|file| file.size).sum();
+      format!("{} ({total_size} bytes)", files.len())
+    }
+  }
+}
+
+async fn create_peer_connection(ice_servers: &[RTCIceServer]) -> Result<Arc<RTCPeerConnection>> {
   let mut media_engine = MediaEngine::default();
   media_engine.register_default_codecs()?;
 
@@ -877,18 +3920,131 @@ async fn create_peer_connection() -> Result<Arc<RTCPeerConnection>> {
     .build();
 
   let config = RTCConfiguration {
-    ice_servers: vec![RTCIceServer {
-      urls: vec!["stun:stun.cloudflare.com:3478".to_string()],
-      ..Default::default()
-    }],
+    ice_servers: ice_servers.to_vec(),
     ..Default::default()
   };
 
-  let pc = api.new_peer_connection(config).await?;
+  let pc = api.new_peer_connection(config).await.context("create peer connection (check ICE servers)")?;
   Ok(Arc::new(pc))
 }
 
+fn resolve_ice_servers(args: &IceArgs) -> Result<Vec<RTCIceServer>> {
+  let servers = if args.no_stun {
+    Vec::new()
+  } else if let Some(listed) = listed_ice_servers(args) {
+    let credential = args.ice_credential.clone().or_else(|| env::var("PAIRLANE_ICE_CREDENTIAL").ok());
+    let mut servers = listed.iter().map(|value| parse_ice_server(value)).collect::<Result<Vec<_>>>()?;
+    if let Some(username) = &args.ice_username {
+      let credential =
+        credential.ok_or_else(|| anyhow!("--ice-username needs --ice-credential or PAIRLANE_ICE_CREDENTIAL"))?;
+      for server in servers.iter_mut().filter(|server| server.urls[0].starts_with("turn") && server.username.is_empty()) {
+        server.username = username.clone();
+        server.credential = credential.clone();
+      }
+    }
+    servers
+  } else if let Some(configured) = load_config()?.ice_servers {
+    configured
+      .into_iter()
+      .map(|server| RTCIceServer {
+        urls: match server.urls {
+          IceUrls::One(url) => vec![url],
+          IceUrls::Many(urls) => urls,
+        },
+        username: server.username,
+        credential: server.credential,
+        ..Default::default()
+      })
+      .collect()
+  } else {
+    vec![RTCIceServer {
+      urls: vec![DEFAULT_STUN_SERVER.to_string()],
+      ..Default::default()
+    }]
+  };
+  let urls: Vec<&str> = servers.iter().flat_map(|server| server.urls.iter().map(String::as_str)).collect();
+  let label = if urls.is_empty() { "none (host candidates only)".to_string() } else { urls.join(", ") };
+  log_line("[rtc] ice servers", &label);
+  Ok(servers)
+}
+
+// --ice-server values, else the comma-separated PAIRLANE_ICE_SERVERS; None when neither is set.
+fn listed_ice_servers(args: &IceArgs) -> Option<Vec<String>> {
+  if !args.ice_servers.is_empty() {
+    return Some(args.ice_servers.clone());
+  }
+  let value = env::var("PAIRLANE_ICE_SERVERS").ok().filter(|value| !value.trim().is_empty())?;
+  Some(
+    value
+      .split(',')
+      .map(str::trim)
+      .filter(|value| !value.is_empty())
+      .map(str::to_string)
+      .collect(),
+  )
+}
+
+// `turn:USER:PASS@host:3478?transport=tcp` -> url `turn:host:3478?transport=tcp` plus credentials,
+// percent-decoded like URL userinfo (so a `:` in the username is written `%3A`).
+fn parse_ice_server(value: &str) -> Result<RTCIceServer> {
+  let (scheme, rest) = value
+    .split_once(':')
+    .filter(|(scheme, _)| matches!(*scheme, "stun" | "stuns" | "turn" | "turns"))
+    .ok_or_else(|| anyhow!("ICE server must start with stun:, stuns:, turn: or turns: ({value})"))?;
+  let (username, credential, host) = match rest.rsplit_once('@') {
+    Some((userinfo, host)) => {
+      let (username, credential) = userinfo
+        .split_once(':')
+        .ok_or_else(|| anyhow!("ICE server credentials must be USER:PASS@ ({scheme}:...)"))?;
+      (percent_decode(username)?, percent_decode(credential)?, host)
+    }
+    None => (String::new(), String::new(), rest),
+  };
+  Ok(RTCIceServer {
+    urls: vec![format!("{scheme}:{host}")],
+    username,
+    credential,
+    ..Default::default()
+  })
+}
+
+fn percent_decode(value: &str) -> Result<String> {
+  percent_encoding::percent_decode_str(value)
+    .decode_utf8()
+    .map(|decoded| decoded.into_owned())
+    .map_err(|_| anyhow!("ICE server credentials must be valid UTF-8 once percent-decoded"))
+}
+
+fn load_config() -> Result<ConfigFile> {
+  let path = match env::var_os("PAIRLANE_CONFIG") {
+    Some(path) => PathBuf::from(path),
+    None => {
+      let base = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")));
+      match base {
+        Some(base) => base.join("pairlane").join("config.json"),
+        None => return Ok(ConfigFile::default()),
+      }
+    }
+  };
+  match std::fs::read_to_string(&path) {
+    Ok(text) => serde_json::from_str(&text).with_context(|| format!("parse {}", path.display())),
+    Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(ConfigFile::default()),
+    Err(err) => Err(anyhow!(err).context(format!("read {}", path.display()))),
+  }
+}
+
 fn parse_room_input(value: &str) -> Result<RoomInput> {
+  if let Some(code) = TransferCode::parse(value) {
+    let code = code?;
+    return Ok(RoomInput {
+      room_id: code.room_id(),
+      endpoint: None,
+      key: None,
+      code: Some(code),
+    });
+  }
   if let Ok(url) = Url::parse(value) {
     return parse_room_url(&url);
   }
@@ -906,6 +4062,7 @@ fn parse_room_input(value: &str) -> Result<RoomInput> {
     room_id: room_id.to_string(),
     endpoint: None,
     key,
+    code: None,
   })
 }
 
@@ -916,7 +4073,12 @@ fn parse_room_url(url: &Url) -> Result<RoomInput> {
     Some(fragment) => parse_key_fragment(fragment)?,
     None => None,
   };
-  Ok(RoomInput { room_id, endpoint, key })
+  Ok(RoomInput {
+    room_id,
+    endpoint,
+    key,
+    code: None,
+  })
 }
 
 fn extract_room_id_from_url(url: &Url) -> Result<String> {
@@ -941,9 +4103,22 @@ fn parse_key_fragment(fragment: &str) -> Result<Option<Vec<u8>>> {
 }
 
 fn build_crypto(key: &[u8]) -> Result<Aes256Gcm> {
+  if key.len() != AES_KEY_LEN {
+    return Err(anyhow!("Invalid encryption key length"));
+  }
   Aes256Gcm::new_from_slice(key).map_err(|_| anyhow!("Invalid encryption key length"))
 }
 
+// HKDF-SHA256 subkey of the room key (or of the code's SPAKE2 output), so the key proof never uses
+// the same key as AES-GCM.
+fn subkey(key: &[u8], info: &[u8]) -> Result<[u8; AES_KEY_LEN]> {
+  let mut okm = [0u8; AES_KEY_LEN];
+  Hkdf::<Sha256>::new(None, key)
+    .expand(info, &mut okm)
+    .map_err(|err| anyhow!("derive key: {err}"))?;
+  Ok(okm)
+}
+
 fn generate_key() -> Result<[u8; AES_KEY_LEN]> {
   let mut key = [0u8; AES_KEY_LEN];
   getrandom(&mut key).map_err(|err| anyhow!(err))?;
@@ -970,6 +4145,87 @@ fn decrypt_frame(crypto: &Aes256Gcm, frame: &[u8]) -> Result<Vec<u8>> {
   crypto.decrypt(nonce, ciphertext).map_err(|err| anyhow!(err))
 }
 
+impl PeerFeatures {
+  fn from_hello(features: &[String]) -> Self {
+    let has = |name: &str| features.iter().any(|feature| feature == name);
+    PeerFeatures {
+      resume: has(FEATURE_RESUME),
+      stream_aead: has(FEATURE_STREAM_AEAD),
+      ack: has(FEATURE_ACK),
+      unknown_length: has(FEATURE_UNSIZED),
+      approve: has(FEATURE_APPROVE),
+      push: has(FEATURE_PUSH),
+    }
+  }
+}
+
+impl StreamCipher {
+  fn new(crypto: Arc<Aes256Gcm>, prefix: [u8; STREAM_PREFIX_LEN], aad: Vec<u8>) -> Self {
+    StreamCipher {
+      crypto,
+      prefix,
+      aad,
+      counter: 0,
+      finished: false,
+    }
+  }
+
+  // Resumed transfers start at a frame boundary; the counter continues from there.
+  fn start_at(&mut self, offset: u64) -> Result<()> {
+    self.counter = u32::try_from(offset / STREAM_CHUNK_SIZE as u64).map_err(|_| anyhow!("file too large for stream framing"))?;
+    Ok(())
+  }
+
+  fn nonce(&self, last: bool) -> [u8; AES_NONCE_LEN] {
+    let mut nonce = [0u8; AES_NONCE_LEN];
+    nonce[..STREAM_PREFIX_LEN].copy_from_slice(&self.prefix);
+    nonce[STREAM_PREFIX_LEN..AES_NONCE_LEN - 1].copy_from_slice(&self.counter.to_be_bytes());
+    nonce[AES_NONCE_LEN - 1] = u8::from(last);
+    nonce
+  }
+
+  fn advance(&mut self, last: bool) -> Result<()> {
+    self.finished = last;
+    if !last {
+      self.counter = self.counter.checked_add(1).ok_or_else(|| anyhow!("stream frame counter overflow"))?;
+    }
+    Ok(())
+  }
+
+  fn seal(&mut self, plain: &[u8], last: bool) -> Result<Vec<u8>> {
+    if self.finished {
+      return Err(anyhow!("encrypted stream already finished"));
+    }
+    let nonce = self.nonce(last);
+    let payload = Payload { msg: plain, aad: &self.aad };
+    let frame = self.crypto.encrypt(Nonce::from_slice(&nonce), payload).map_err(|err| anyhow!(err))?;
+    self.advance(last)?;
+    Ok(frame)
+  }
+
+  fn open(&mut self, frame: &[u8]) -> Result<Vec<u8>> {
+    if self.finished {
+      return Err(anyhow!("data received after the final encrypted frame"));
+    }
+    for last in [false, true] {
+      let nonce = self.nonce(last);
+      let payload = Payload { msg: frame, aad: &self.aad };
+      if let Ok(plain) = self.crypto.decrypt(Nonce::from_slice(&nonce), payload) {
+        self.advance(last)?;
+        return Ok(plain);
+      }
+    }
+    Err(anyhow!("encrypted frame {} failed to authenticate (dropped, reordered or tampered)", self.counter))
+  }
+}
+
+// Binds every frame to the room and to the announced file, so frames cannot be spliced between files.
+// `size` and `sha256` are null for a stream of unknown length.
+fn stream_aad(room_id: &str, name: &str, size: Option<u64>, mime: &str, sha256: Option<&str>) -> Vec<u8> {
+  serde_json::to_vec(&serde_json::json!(["pairlane-stream-v2", room_id, name, size, mime, sha256]))
+    .expect("serialize stream aad")
+}
+
 fn b64url_encode(value: &[u8]) -> String {
   URL_SAFE_NO_PAD.encode(value)
 }
@@ -978,6 +4234,10 @@ fn b64url_decode(value: &str) -> Result<Vec<u8>> {
   URL_SAFE_NO_PAD.decode(value).map_err(|err| anyhow!(err))
 }
 
+fn to_hex(bytes: &[u8]) -> String {
+  bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
 fn sanitize_file_name(name: &str) -> String {
   let candidate = Path::new(name)
     .file_name()
@@ -991,15 +4251,43 @@ fn sanitize_file_name(name: &str) -> String {
   }
 }
 
-async fn wait_for_drain(dc: &RTCDataChannel) {
-  for _ in 0..500 {
+// Keeps the directory layout of `name` but drops `..`, `.`, roots and drive prefixes so the
+// result always stays under the output directory.
+fn sanitize_relative_path(name: &str) -> PathBuf {
+  let mut path = PathBuf::new();
+  for component in name.split(['/', '\\']) {
+    let trimmed = component.trim();
+    if trimmed.is_empty() || trimmed == "." || trimmed == ".." {
+      continue;
+    }
+    path.push(sanitize_file_name(trimmed));
+  }
+  if path.as_os_str().is_empty() {
+    path.push("file");
+  }
+  path
+}
+
+// Waits until at most `limit` bytes are queued on the channel. `buffer_low` fires when the queue
+// crosses the low threshold; polling covers the final drain to zero and any missed wakeup.
+async fn wait_for_buffered(dc: &RTCDataChannel, buffer_low: &Notify, limit: usize) -> Result<()> {
+  let mut smallest = usize::MAX;
+  let mut last_progress = tokio::time::Instant::now();
+  loop {
+    let amount = dc.buffered_amount().await;
+    if amount <= limit {
+      return Ok(());
+    }
     if dc.ready_state() != RTCDataChannelState::Open {
-      break;
+      return Err(ErrorKind::PeerAbort.error(format!("data channel closed with {amount} bytes still queued")));
     }
-    if dc.buffered_amount().await == 0 {
-      break;
+    if amount < smallest {
+      smallest = amount;
+      last_progress = tokio::time::Instant::now();
+    } else if last_progress.elapsed() > BUFFER_STALL_TIMEOUT {
+      return Err(ErrorKind::Timeout.error(format!("send stalled with {amount} bytes queued")));
     }
-    sleep(Duration::from_millis(10)).await;
+    let _ = timeout(BUFFER_POLL_INTERVAL, buffer_low.notified()).await;
   }
 }
 
@@ -1054,13 +4342,44 @@ fn base_endpoint_url(endpoint: Option<&str>) -> Result<Url> {
   Ok(url)
 }
 
-async fn create_room(endpoint: Option<&str>, creator_cid: Option<&str>) -> Result<String> {
-  #[derive(Serialize)]
-  struct RoomRequest {
-    #[serde(rename = "creatorCid", skip_serializing_if = "Option::is_none")]
-    creator_cid: Option<String>,
+// Connects as the offerer of a fresh code room. The first client in a room becomes its offerer,
+// so a nameplate someone else is already sending on comes back as `answerer` and we draw again.
+async fn claim_code_room(endpoint: Option<&str>, client_id: &str) -> Result<(TransferCode, SignalingStream)> {
+  for _ in 0..CODE_CLAIM_ATTEMPTS {
+    let code = TransferCode::generate()?;
+    let ws_url = build_ws_url(endpoint, &code.room_id(), client_id)?;
+    log_line("[ws] connecting", ws_url.as_str());
+    let (mut ws_stream, _) = connect_async(ws_url.to_string())
+      .await
+      .context("connect signaling websocket")?;
+    while let Some(msg) = ws_stream.next().await {
+      if let Message::Text(text) = msg.context("websocket read")? {
+        if let Ok(ServerMessage::Role { role, cid }) = serde_json::from_str(&text) {
+          log_line("[ws] role", &format!("{role} ({cid})"));
+          if role == "offerer" {
+            return Ok((code, ws_stream));
+          }
+          break;
+        }
+      }
+    }
+    let _ = ws_stream.close(None).await;
   }
+  Err(ErrorKind::Signaling.error(format!("No free code room after {CODE_CLAIM_ATTEMPTS} attempts; try again")))
+}
+
+// The body of `POST /api/rooms`; whatever is left out takes the server's default.
+#[derive(Debug, Serialize)]
+struct RoomOptions {
+  // Only this client may become the room's sender.
+  #[serde(rename = "creatorCid", skip_serializing_if = "Option::is_none")]
+  creator_cid: Option<String>,
+  // How many receivers may transfer at once; the server clamps it to 1-10.
+  #[serde(rename = "maxConcurrent", skip_serializing_if = "Option::is_none")]
+  max_concurrent: Option<u32>,
+}
 
+async fn create_room(endpoint: Option<&str>, options: &RoomOptions) -> Result<String> {
   #[derive(Deserialize)]
   struct RoomResponse {
     #[serde(rename = "roomId")]
@@ -1072,9 +4391,7 @@ async fn create_room(endpoint: Option<&str>, creator_cid: Option<&str>) -> Resul
   let client = reqwest::Client::new();
   let response = client
     .post(url)
-    .json(&RoomRequest {
-      creator_cid: creator_cid.map(|value| value.to_string()),
-    })
+    .json(options)
     .send()
     .await
     .context("create room request")?;
@@ -1083,7 +4400,469 @@ async fn create_room(endpoint: Option<&str>, creator_cid: Option<&str>) -> Resul
   Ok(body.room_id)
 }
 
+// `room info`: what `GET /api/rooms/:id` reports, so a link can be checked before it is shared.
+async fn run_room_info(room_input: &str, endpoint: Option<&str>) -> Result<()> {
+  #[derive(Deserialize)]
+  struct RoomInfo {
+    #[serde(rename = "maxConcurrent")]
+    max_concurrent: u32,
+  }
+
+  let parsed = parse_room_input(room_input)?;
+  let mut url = base_endpoint_url(endpoint.or(parsed.endpoint.as_deref()))?;
+  url
+    .path_segments_mut()
+    .map_err(|_| anyhow!("Invalid endpoint URL"))?
+    .extend(["api", "rooms", &parsed.room_id]);
+  let response = reqwest::get(url).await.context("room info request")?;
+  if response.status() == reqwest::StatusCode::NOT_FOUND {
+    return Err(ErrorKind::Signaling.error(format!("room {} does not exist", parsed.room_id)));
+  }
+  let response = response.error_for_status().context("room info response")?;
+  let info: RoomInfo = response.json().await.context("parse room info response")?;
+  if events::enabled() {
+    events::emit(Event::RoomInfo {
+      id: &parsed.room_id,
+      max_concurrent: info.max_concurrent,
+    });
+  } else {
+    println!("room: {}", parsed.room_id);
+    println!("max concurrent: {}", info.max_concurrent);
+  }
+  Ok(())
+}
+
 fn log_line(label: &str, value: &str) {
   let now = chrono::Utc::now().format("%H:%M:%S%.3f");
-  println!("[{now}] {label}: {value}");
+  progress::print_line(&format!("[{now}] {label}: {value}"));
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::path::Component;
+
+  fn existing() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("Cargo.toml")
+  }
+
+  #[test]
+  fn split_send_inputs_takes_a_room_shaped_last_argument() {
+    for room in ["ABCDEFGH23", "ABCDEFGH23#k=abc", "7-crossword-banana", "https://getpairlane.com/r/ABCDEFGH23#k=abc"] {
+      let (paths, found) = split_send_inputs(vec![existing(), PathBuf::from(room)]).unwrap();
+      assert_eq!(paths, vec![existing()]);
+      assert_eq!(found.as_deref(), Some(room));
+    }
+  }
+
+  #[test]
+  fn split_send_inputs_keeps_a_missing_file_as_a_path() {
+    for missing in ["missing.txt", "notes", "abcdefgh23", "C:\\missing.txt", "-"] {
+      let (paths, found) = split_send_inputs(vec![existing(), PathBuf::from(missing)]).unwrap();
+      assert_eq!(paths, vec![existing(), PathBuf::from(missing)]);
+      assert_eq!(found, None);
+    }
+  }
+
+  #[test]
+  fn split_send_inputs_leaves_a_single_path_or_existing_paths_alone() {
+    let (paths, found) = split_send_inputs(vec![PathBuf::from("ABCDEFGH23")]).unwrap();
+    assert_eq!(paths, vec![PathBuf::from("ABCDEFGH23")]);
+    assert_eq!(found, None);
+    let (paths, found) = split_send_inputs(vec![existing(), existing()]).unwrap();
+    assert_eq!(paths.len(), 2);
+    assert_eq!(found, None);
+  }
+
+  fn stream_pair(aad: &[u8]) -> (StreamCipher, StreamCipher) {
+    let crypto = Arc::new(build_crypto(&[7u8; AES_KEY_LEN]).unwrap());
+    let prefix = [1u8; STREAM_PREFIX_LEN];
+    (
+      StreamCipher::new(crypto.clone(), prefix, aad.to_vec()),
+      StreamCipher::new(crypto, prefix, aad.to_vec()),
+    )
+  }
+
+  fn sealed_frames(sealer: &mut StreamCipher) -> Vec<Vec<u8>> {
+    vec![
+      sealer.seal(b"one", false).unwrap(),
+      sealer.seal(b"two", false).unwrap(),
+      sealer.seal(b"three", true).unwrap(),
+    ]
+  }
+
+  #[test]
+  fn stream_cipher_round_trips_in_order() {
+    let (mut sealer, mut opener) = stream_pair(b"aad");
+    let frames = sealed_frames(&mut sealer);
+    let opened: Vec<Vec<u8>> = frames.iter().map(|frame| opener.open(frame).unwrap()).collect();
+    assert_eq!(opened, vec![b"one".to_vec(), b"two".to_vec(), b"three".to_vec()]);
+    assert!(opener.finished);
+  }
+
+  #[test]
+  fn stream_cipher_notices_truncation() {
+    let (mut sealer, mut opener) = stream_pair(b"aad");
+    let frames = sealed_frames(&mut sealer);
+    opener.open(&frames[0]).unwrap();
+    opener.open(&frames[1]).unwrap();
+    // Cut before the last frame: everything opens, but the stream never finishes.
+    assert!(!opener.finished);
+    // A dropped frame shows up as the next one failing.
+    let (_, mut opener) = stream_pair(b"aad");
+    opener.open(&frames[0]).unwrap();
+    assert!(opener.open(&frames[2]).is_err());
+  }
+
+  #[test]
+  fn stream_cipher_rejects_reordered_and_spliced_frames() {
+    let (mut sealer, mut opener) = stream_pair(b"aad");
+    let frames = sealed_frames(&mut sealer);
+    assert!(opener.open(&frames[1]).is_err());
+    let (_, mut other_file) = stream_pair(b"other aad");
+    assert!(other_file.open(&frames[0]).is_err());
+    let mut tampered = frames[0].clone();
+    tampered[0] ^= 1;
+    let (_, mut opener) = stream_pair(b"aad");
+    assert!(opener.open(&tampered).is_err());
+  }
+
+  #[test]
+  fn stream_cipher_ends_at_the_last_flag() {
+    let (mut sealer, mut opener) = stream_pair(b"aad");
+    let last = sealer.seal(b"only", true).unwrap();
+    assert!(sealer.seal(b"more", false).is_err());
+    assert_eq!(opener.open(&last).unwrap(), b"only");
+    assert!(opener.finished);
+    assert!(opener.open(&last).is_err());
+    // Without the flag the stream stays open, so the receiver still waits for the end.
+    let (mut sealer, _) = stream_pair(b"aad");
+    let middle = sealer.seal(b"middle", false).unwrap();
+    let (_, mut opener) = stream_pair(b"aad");
+    opener.open(&middle).unwrap();
+    assert!(!opener.finished);
+  }
+
+  #[test]
+  fn stream_cipher_resumes_at_a_frame_boundary() {
+    let (mut sealer, _) = stream_pair(b"aad");
+    let frames = sealed_frames(&mut sealer);
+    let (_, mut opener) = stream_pair(b"aad");
+    opener.start_at(2 * STREAM_CHUNK_SIZE as u64).unwrap();
+    assert_eq!(opener.open(&frames[2]).unwrap(), b"three");
+    assert!(opener.finished);
+  }
+
+  #[tokio::test]
+  async fn hash_prefix_reads_exactly_the_resumed_part() {
+    let data = b"resumed part and the rest".to_vec();
+    let mut source = data.as_slice();
+    let mut hasher = Sha256::new();
+    hash_prefix(&mut source, 12, &mut hasher).await.unwrap();
+    hasher.update(source);
+    assert_eq!(to_hex(&hasher.finalize()), to_hex(&Sha256::digest(&data)));
+
+    let mut short = &data[..4];
+    assert!(hash_prefix(&mut short, 12, &mut Sha256::new()).await.is_err());
+  }
+
+  fn proof_hex(key: &[u8], room_id: &str, nonce: &[u8]) -> String {
+    to_hex(&key_proof(key, room_id, nonce).unwrap().finalize().into_bytes())
+  }
+
+  #[test]
+  fn build_crypto_encrypts_with_the_room_key_as_is() {
+    // Browsers without the key proof decrypt with the raw room key, so this must stay interoperable.
+    let key = [7u8; AES_KEY_LEN];
+    let nonce = Nonce::from_slice(&[1u8; 12]);
+    let sealed = build_crypto(&key).unwrap().encrypt(nonce, b"data".as_ref()).unwrap();
+    let raw = Aes256Gcm::new_from_slice(&key).unwrap();
+    assert_eq!(raw.decrypt(nonce, sealed.as_ref()).unwrap(), b"data");
+  }
+
+  #[test]
+  fn key_proof_uses_the_auth_subkey() {
+    let nonce: Vec<u8> = (0..AUTH_NONCE_LEN as u8).collect();
+    // HMAC-SHA256(HKDF-SHA256(key, info "pairlane-auth-v1"), "pairlane-auth-v1\0" + room + "\0" + nonce),
+    // worked out independently; the browser must arrive at the same value.
+    assert_eq!(
+      proof_hex(&[7u8; AES_KEY_LEN], "ROOM123456", &nonce),
+      "2166ef75065aac0914ca3d2eb92a0e0879461f4b43362733e034ad94ea2f07ba"
+    );
+  }
+
+  #[test]
+  fn key_proof_depends_on_key_room_and_nonce() {
+    let nonce = [3u8; AUTH_NONCE_LEN];
+    let proof = proof_hex(&[7u8; AES_KEY_LEN], "ROOM123456", &nonce);
+    assert_ne!(proof, proof_hex(&[8u8; AES_KEY_LEN], "ROOM123456", &nonce));
+    assert_ne!(proof, proof_hex(&[7u8; AES_KEY_LEN], "ROOM654321", &nonce));
+    assert_ne!(proof, proof_hex(&[7u8; AES_KEY_LEN], "ROOM123456", &[4u8; AUTH_NONCE_LEN]));
+    let mac = b64url_encode(&key_proof(&[7u8; AES_KEY_LEN], "ROOM123456", &nonce).unwrap().finalize().into_bytes());
+    assert!(key_proof(&[7u8; AES_KEY_LEN], "ROOM123456", &nonce)
+      .unwrap()
+      .verify_slice(&b64url_decode(&mac).unwrap())
+      .is_ok());
+  }
+
+  fn ice(value: &str) -> (Vec<String>, String, String) {
+    let server = parse_ice_server(value).unwrap();
+    (server.urls, server.username, server.credential)
+  }
+
+  #[test]
+  fn parse_ice_server_splits_off_credentials() {
+    assert_eq!(ice("stun:stun.example.com:3478"), (vec!["stun:stun.example.com:3478".to_string()], String::new(), String::new()));
+    assert_eq!(
+      ice("turn:alice:secret@turn.example.com:3478?transport=tcp"),
+      (vec!["turn:turn.example.com:3478?transport=tcp".to_string()], "alice".to_string(), "secret".to_string())
+    );
+  }
+
+  #[test]
+  fn parse_ice_server_decodes_percent_escapes() {
+    let (urls, username, credential) = ice("turns:al%3Aice:p%40ss%3Aw%25rd@turn.example.com:5349");
+    assert_eq!(urls, vec!["turns:turn.example.com:5349".to_string()]);
+    assert_eq!(username, "al:ice");
+    assert_eq!(credential, "p@ss:w%rd");
+    // Unescaped, a password still runs from the first `:` to the last `@`.
+    let (_, username, credential) = ice("turn:alice:p:a@ss@turn.example.com");
+    assert_eq!((username.as_str(), credential.as_str()), ("alice", "p:a@ss"));
+  }
+
+  #[test]
+  fn parse_ice_server_rejects_bad_input() {
+    for value in ["http://turn.example.com", "turn.example.com:3478", "turn:alice@turn.example.com", "turn:alice:%FF@turn.example.com"] {
+      assert!(parse_ice_server(value).is_err(), "{value}");
+    }
+  }
+
+  #[test]
+  fn sanitize_relative_path_stays_under_the_output_directory() {
+    for name in ["../../etc/passwd", "/etc/passwd", "\\\\server\\share\\x", "C:\\Windows\\x", "a/../../b", "./a/./b"] {
+      let path = sanitize_relative_path(name);
+      assert!(path.components().all(|component| matches!(component, Component::Normal(_))), "{name} -> {}", path.display());
+    }
+    assert_eq!(sanitize_relative_path("../../etc/passwd"), PathBuf::from("etc/passwd"));
+    assert_eq!(sanitize_relative_path("/abs/file.txt"), PathBuf::from("abs/file.txt"));
+    assert_eq!(sanitize_relative_path("dir\\sub\\file.txt"), PathBuf::from("dir/sub/file.txt"));
+  }
+
+  #[test]
+  fn sanitize_relative_path_trims_spaces_and_falls_back_to_file() {
+    assert_eq!(sanitize_relative_path(" docs /notes.txt "), PathBuf::from("docs/notes.txt"));
+    for name in ["", "..", ".", "/", " / ", "../.."] {
+      assert_eq!(sanitize_relative_path(name), PathBuf::from("file"), "{name:?}");
+    }
+  }
+
+  // A fresh directory under the system temp dir, unique to the test and this run.
+  fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("pairlane-test-{}-{name}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+  }
+
+  fn receive_progress(output_dir: &Path, on_conflict: OnConflict) -> ReceiveProgress {
+    let options = ReceiveOptions {
+      approval: approval::Approval::Auto,
+      prompter: Arc::new(approval::Prompter::default()),
+      limits: approval::Limits {
+        max_size: None,
+        allow_mime: Vec::new(),
+        deny_ext: Vec::new(),
+      },
+      exec: None,
+      on_complete: None,
+      on_conflict,
+      stay_open: false,
+    };
+    let (outcomes, _) = mpsc::unbounded_channel();
+    ReceiveProgress::new(output_dir, options, "ROOM123456", None, None, None, outcomes)
+  }
+
+  #[test]
+  fn part_path_for_tags_known_sizes() {
+    assert_eq!(part_path_for(Path::new("out/a.txt"), Some(42)), PathBuf::from("out/a.txt.42.part"));
+    assert_eq!(part_path_for(Path::new("out/a.txt"), None), PathBuf::from("out/a.txt.part"));
+  }
+
+  #[tokio::test]
+  async fn open_output_file_starts_a_new_part_from_zero() {
+    let dir = scratch_dir("part-new");
+    let mut progress = receive_progress(&dir, OnConflict::Rename);
+    let offset = open_output_file(&mut progress, Path::new("sub/a.bin"), Some(10), None, 1).await.unwrap();
+    assert_eq!(offset, 0);
+    assert_eq!(progress.part_file.as_deref(), Some(dir.join("sub/a.bin.10.part").as_path()));
+    assert_eq!(progress.current_file.as_deref(), Some(dir.join("sub/a.bin").as_path()));
+    assert!(dir.join("sub/a.bin.10.part").exists());
+    let _ = std::fs::remove_dir_all(&dir);
+  }
+
+  #[tokio::test]
+  async fn open_output_file_resumes_and_hashes_what_is_on_disk() {
+    let dir = scratch_dir("part-resume");
+    std::fs::write(dir.join("a.bin.10.part"), b"0123").unwrap();
+    let mut progress = receive_progress(&dir, OnConflict::Rename);
+    let offset = open_output_file(&mut progress, Path::new("a.bin"), Some(10), None, 1).await.unwrap();
+    assert_eq!((offset, progress.received), (4, 4));
+    progress.hasher.update(b"456789");
+    let hash = to_hex(&std::mem::take(&mut progress.hasher).finalize());
+    assert_eq!(hash, to_hex(&Sha256::digest(b"0123456789")));
+    let _ = std::fs::remove_dir_all(&dir);
+  }
+
+  #[tokio::test]
+  async fn open_output_file_truncates_to_the_frame_boundary() {
+    let dir = scratch_dir("part-align");
+    std::fs::write(dir.join("a.bin.100.part"), [7u8; 70]).unwrap();
+    let mut progress = receive_progress(&dir, OnConflict::Rename);
+    let offset = open_output_file(&mut progress, Path::new("a.bin"), Some(100), None, 32).await.unwrap();
+    assert_eq!(offset, 64);
+    drop(progress.file.take());
+    assert_eq!(std::fs::metadata(dir.join("a.bin.100.part")).unwrap().len(), 64);
+    let _ = std::fs::remove_dir_all(&dir);
+  }
+
+  #[tokio::test]
+  async fn open_output_file_discards_a_part_that_cannot_belong() {
+    let dir = scratch_dir("part-discard");
+    // Longer than the announced size, so it is from some other file.
+    std::fs::write(dir.join("a.bin.10.part"), [7u8; 20]).unwrap();
+    // Streams of unknown length never resume.
+    std::fs::write(dir.join("b.bin.part"), [7u8; 20]).unwrap();
+    let mut progress = receive_progress(&dir, OnConflict::Rename);
+    assert_eq!(open_output_file(&mut progress, Path::new("a.bin"), Some(10), None, 1).await.unwrap(), 0);
+    drop(progress.file.take());
+    assert_eq!(std::fs::metadata(dir.join("a.bin.10.part")).unwrap().len(), 0);
+    assert_eq!(open_output_file(&mut progress, Path::new("b.bin"), None, None, 1).await.unwrap(), 0);
+    drop(progress.file.take());
+    assert_eq!(std::fs::metadata(dir.join("b.bin.part")).unwrap().len(), 0);
+    let _ = std::fs::remove_dir_all(&dir);
+  }
+
+  #[test]
+  fn numbered_path_goes_before_the_extension() {
+    assert_eq!(numbered_path(Path::new("out/a.txt"), 1), PathBuf::from("out/a (1).txt"));
+    assert_eq!(numbered_path(Path::new("out/a.tar.gz"), 2), PathBuf::from("out/a.tar (2).gz"));
+    assert_eq!(numbered_path(Path::new("out/README"), 3), PathBuf::from("out/README (3)"));
+  }
+
+  #[tokio::test]
+  async fn commit_part_never_replaces_a_file_unless_overwriting() {
+    let dir = scratch_dir("commit");
+    let part = dir.join("a.txt.3.part");
+    std::fs::write(&part, b"new").unwrap();
+    assert_eq!(commit_part(&part, &dir.join("a.txt"), OnConflict::Rename).await.unwrap(), Some(dir.join("a.txt")));
+    assert!(!part.exists());
+
+    std::fs::write(&part, b"new").unwrap();
+    assert_eq!(commit_part(&part, &dir.join("a.txt"), OnConflict::Rename).await.unwrap(), Some(dir.join("a (1).txt")));
+    std::fs::write(&part, b"new").unwrap();
+    assert_eq!(commit_part(&part, &dir.join("a.txt"), OnConflict::Rename).await.unwrap(), Some(dir.join("a (2).txt")));
+
+    std::fs::write(dir.join("b.txt"), b"old").unwrap();
+    std::fs::write(&part, b"new").unwrap();
+    assert_eq!(commit_part(&part, &dir.join("b.txt"), OnConflict::Skip).await.unwrap(), None);
+    assert!(!part.exists());
+    assert_eq!(std::fs::read(dir.join("b.txt")).unwrap(), b"old");
+
+    std::fs::write(&part, b"new").unwrap();
+    assert_eq!(commit_part(&part, &dir.join("b.txt"), OnConflict::Overwrite).await.unwrap(), Some(dir.join("b.txt")));
+    assert_eq!(std::fs::read(dir.join("b.txt")).unwrap(), b"new");
+    assert!(!part.exists());
+    let _ = std::fs::remove_dir_all(&dir);
+  }
+
+  #[tokio::test]
+  async fn resolve_conflict_applies_the_policy_only_to_taken_paths() {
+    let dir = scratch_dir("conflict");
+    let free = dir.join("free.txt");
+    let taken = dir.join("taken.txt");
+    std::fs::write(&taken, b"old").unwrap();
+    std::fs::write(dir.join("taken (1).txt"), b"old").unwrap();
+
+    let mut progress = receive_progress(&dir, OnConflict::Rename);
+    assert_eq!(resolve_conflict(&mut progress, free.clone()).await.unwrap(), Some(free.clone()));
+    assert_eq!(resolve_conflict(&mut progress, taken.clone()).await.unwrap(), Some(dir.join("taken (2).txt")));
+
+    let mut progress = receive_progress(&dir, OnConflict::Skip);
+    assert_eq!(resolve_conflict(&mut progress, taken.clone()).await.unwrap(), None);
+    assert_eq!(progress.conflict, OnConflict::Skip);
+
+    let mut progress = receive_progress(&dir, OnConflict::Overwrite);
+    assert_eq!(resolve_conflict(&mut progress, taken.clone()).await.unwrap(), Some(taken.clone()));
+
+    // Nothing to ask about, and a file that turns up later is renamed rather than asked about.
+    let mut progress = receive_progress(&dir, OnConflict::Ask);
+    assert_eq!(resolve_conflict(&mut progress, free.clone()).await.unwrap(), Some(free));
+    assert_eq!(progress.conflict, OnConflict::Rename);
+    let _ = std::fs::remove_dir_all(&dir);
+  }
+
+  fn offered(name: &str, size: Option<u64>) -> approval::OfferedFile {
+    approval::OfferedFile {
+      name: name.to_string(),
+      size,
+      mime: None,
+    }
+  }
+
+  #[test]
+  fn take_manifest_entries_holds_each_meta_to_an_unused_entry() {
+    let mut manifest = vec![("a.txt".to_string(), Some(10)), ("a.txt".to_string(), Some(20)), ("live.log".to_string(), None)];
+    assert!(take_manifest_entries(&mut manifest, &[offered("a.txt", Some(15))]).is_ok());
+    assert!(take_manifest_entries(&mut manifest, &[offered("a.txt", Some(15))]).unwrap_err().contains("larger"));
+    assert!(take_manifest_entries(&mut manifest, &[offered("a.txt", None)]).is_err());
+    assert!(take_manifest_entries(&mut manifest, &[offered("a.txt", Some(10))]).is_ok());
+    assert!(take_manifest_entries(&mut manifest, &[offered("a.txt", Some(1))]).unwrap_err().contains("not in"));
+    assert!(take_manifest_entries(&mut manifest, &[offered("other.txt", Some(1))]).unwrap_err().contains("not in"));
+    assert!(take_manifest_entries(&mut manifest, &[offered("live.log", Some(1 << 40))]).is_ok());
+    assert!(manifest.is_empty());
+  }
+
+  #[test]
+  fn check_overrun_stops_at_the_announced_size_then_the_limit() {
+    let dir = scratch_dir("overrun-check");
+    let mut progress = receive_progress(&dir, OnConflict::Rename);
+    progress.options.limits.max_size = Some(100);
+    progress.expected_size = Some(10);
+    assert!(check_overrun(&progress, 10).is_ok());
+    assert!(matches!(check_overrun(&progress, 11), Err(Overrun::Announced(10))));
+    progress.expected_size = None;
+    assert!(check_overrun(&progress, 100).is_ok());
+    assert!(matches!(check_overrun(&progress, 101), Err(Overrun::Limit(reason)) if reason.contains("--max-size")));
+    let _ = std::fs::remove_dir_all(&dir);
+  }
+
+  #[tokio::test]
+  async fn an_overrun_fails_the_file_without_waiting_for_done() {
+    let dir = scratch_dir("overrun-abort");
+    let mut progress = receive_progress(&dir, OnConflict::Rename);
+    open_output_file(&mut progress, Path::new("a.bin"), Some(4), None, 1).await.unwrap();
+    progress.received = 4;
+    progress.error = Some(ErrorKind::Integrity.error("received more than the announced 4 bytes"));
+    let (finished, mut finished_rx) = mpsc::unbounded_channel();
+    complete_current_file(&mut progress, None, &finished);
+    let file = finished_rx.try_recv().unwrap();
+    let err = file.result.unwrap_err();
+    assert_eq!(error::kind_of(&err), Some(ErrorKind::Integrity));
+    assert_eq!(file.part_path.as_deref(), Some(dir.join("a.bin.4.part").as_path()));
+    assert!(progress.current_file.is_none() && progress.file.is_none() && progress.error.is_none());
+    let _ = std::fs::remove_dir_all(&dir);
+  }
+
+  #[test]
+  fn receive_summary_counts_every_transfer() {
+    let mut summary = ReceiveSummary::default();
+    summary.record(&Ok(()));
+    summary.files = 1;
+    summary.bytes = 2048;
+    assert_eq!(summary.describe(), "1 file (2.0 KiB) in 1 transfer");
+    summary.record(&Err(anyhow!("declined")));
+    summary.files = 3;
+    summary.skipped = 1;
+    assert_eq!(summary.describe(), "3 files (2.0 KiB) in 2 transfers, 1 skipped, 1 failed");
+  }
 }
@@ -0,0 +1,352 @@
+// Design: live progress for `send` and `receive`. On a terminal the active transfers are redrawn in
+// place below the log, one row per file in flight; otherwise a plain progress line per transfer is
+// logged every few seconds so redirected output stays readable. With `--json` the rows become
+// `progress` events and log lines go to stderr, as they do when stdout carries the received data.
+
+use std::io::{IsTerminal, Write};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use crate::events::{self, Event};
+use crate::log_line;
+
+const REDRAW_INTERVAL: Duration = Duration::from_millis(250);
+const PLAIN_INTERVAL: Duration = Duration::from_secs(5);
+const EVENT_INTERVAL: Duration = Duration::from_secs(1);
+// Weight of the newest sample in the smoothed transfer rate.
+const RATE_SMOOTHING: f64 = 0.3;
+const BAR_WIDTH: usize = 20;
+const NAME_WIDTH: usize = 28;
+const PEER_WIDTH: usize = 8;
+// Stored as the total of a stream whose length is only known once it ends.
+const UNKNOWN_TOTAL: u64 = u64::MAX;
+
+static STDOUT_RESERVED: AtomicBool = AtomicBool::new(false);
+
+struct Board {
+  tty: bool,
+  json: bool,
+  // Log lines go to stderr because stdout carries JSON events or file data.
+  stderr: bool,
+  rows: Vec<Arc<RowState>>,
+  // Lines printed by the last redraw; erased before anything else goes to stdout.
+  drawn: usize,
+  ticking: bool,
+}
+
+struct RowState {
+  scope: &'static str,
+  peer: Option<String>,
+  name: Mutex<String>,
+  total: AtomicU64,
+  done: AtomicU64,
+  rate: Mutex<RateSample>,
+}
+
+struct RateSample {
+  at: Instant,
+  done: u64,
+  bytes_per_sec: Option<f64>,
+}
+
+// One transfer's row. Counters are plain atomics, so updating them from the hot path is cheap; the
+// row leaves the display on `finish` or when dropped (e.g. when the transfer fails).
+pub struct ProgressRow(Arc<RowState>);
+
+impl ProgressRow {
+  pub fn new(scope: &'static str, peer: Option<String>) -> Self {
+    ProgressRow(Arc::new(RowState {
+      scope,
+      peer,
+      name: Mutex::new(String::new()),
+      total: AtomicU64::new(0),
+      done: AtomicU64::new(0),
+      rate: Mutex::new(RateSample {
+        at: Instant::now(),
+        done: 0,
+        bytes_per_sec: None,
+      }),
+    }))
+  }
+
+  // Shows the row for a new file; `done` is non-zero when resuming, `total` is None for a stream.
+  pub fn start(&self, name: &str, total: Option<u64>, done: u64) {
+    *lock(&self.0.name) = name.to_string();
+    self.0.total.store(total.unwrap_or(UNKNOWN_TOTAL), Ordering::Relaxed);
+    self.0.done.store(done, Ordering::Relaxed);
+    *lock(&self.0.rate) = RateSample {
+      at: Instant::now(),
+      done,
+      bytes_per_sec: None,
+    };
+    let mut board = lock(board());
+    if !board.rows.iter().any(|row| Arc::ptr_eq(row, &self.0)) {
+      board.rows.push(self.0.clone());
+    }
+    if !board.ticking {
+      board.ticking = true;
+      std::thread::spawn(tick);
+    }
+  }
+
+  pub fn set(&self, done: u64) {
+    self.0.done.store(done, Ordering::Relaxed);
+  }
+
+  pub fn finish(&self) {
+    let mut board = lock(board());
+    let before = board.rows.len();
+    board.rows.retain(|row| !Arc::ptr_eq(row, &self.0));
+    if board.rows.len() != before && board.tty {
+      clear(&mut board);
+      draw(&mut board);
+    }
+  }
+}
+
+impl Drop for ProgressRow {
+  fn drop(&mut self) {
+    self.finish();
+  }
+}
+
+// Keeps stdout free for data (`receive -o -`); must be called before the first log line.
+pub fn reserve_stdout() {
+  STDOUT_RESERVED.store(true, Ordering::Relaxed);
+}
+
+pub fn stdout_reserved() -> bool {
+  STDOUT_RESERVED.load(Ordering::Relaxed)
+}
+
+// Prints a log line without tearing the rows drawn below it.
+pub fn print_line(line: &str) {
+  let mut board = lock(board());
+  if board.stderr {
+    eprintln!("{line}");
+    return;
+  }
+  clear(&mut board);
+  println!("{line}");
+  draw(&mut board);
+}
+
+fn board() -> &'static Mutex<Board> {
+  static BOARD: OnceLock<Mutex<Board>> = OnceLock::new();
+  BOARD.get_or_init(|| {
+    let json = events::enabled();
+    let stderr = json || stdout_reserved();
+    Mutex::new(Board {
+      tty: !stderr && std::io::stdout().is_terminal(),
+      json,
+      stderr,
+      rows: Vec::new(),
+      drawn: 0,
+      ticking: false,
+    })
+  })
+}
+
+// A panic while printing must not take the progress display (and every log line) down with it.
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+  mutex.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+fn tick() {
+  let (tty, json) = {
+    let board = lock(board());
+    (board.tty, board.json)
+  };
+  let interval = match (tty, json) {
+    (true, _) => REDRAW_INTERVAL,
+    (_, true) => EVENT_INTERVAL,
+    _ => PLAIN_INTERVAL,
+  };
+  loop {
+    std::thread::sleep(interval);
+    let rows = lock(board()).rows.clone();
+    for row in &rows {
+      row.sample();
+    }
+    if tty {
+      let mut board = lock(board());
+      clear(&mut board);
+      draw(&mut board);
+    } else if json {
+      for row in &rows {
+        row.emit();
+      }
+    } else {
+      for row in &rows {
+        log_line(&format!("{} progress", row.scope), &row.summary());
+      }
+    }
+  }
+}
+
+fn clear(board: &mut Board) {
+  if board.drawn == 0 {
+    return;
+  }
+  // Move to the first drawn line and erase everything below it.
+  print!("\x1b[{}F\x1b[J", board.drawn);
+  board.drawn = 0;
+}
+
+fn draw(board: &mut Board) {
+  if !board.tty || board.rows.is_empty() {
+    return;
+  }
+  let mut out = std::io::stdout().lock();
+  for row in &board.rows {
+    let _ = writeln!(out, "{}", row.render());
+  }
+  let _ = out.flush();
+  board.drawn = board.rows.len();
+}
+
+impl RowState {
+  fn sample(&self) {
+    let done = self.done.load(Ordering::Relaxed);
+    let mut rate = lock(&self.rate);
+    let elapsed = rate.at.elapsed().as_secs_f64();
+    if elapsed <= 0.0 {
+      return;
+    }
+    let current = done.saturating_sub(rate.done) as f64 / elapsed;
+    rate.bytes_per_sec = Some(match rate.bytes_per_sec {
+      Some(previous) => previous + RATE_SMOOTHING * (current - previous),
+      None => current,
+    });
+    rate.at = Instant::now();
+    rate.done = done;
+  }
+
+  fn emit(&self) {
+    let (done, total, rate) = self.figures();
+    events::emit(Event::Progress {
+      peer_id: self.peer.as_deref(),
+      name: &lock(&self.name),
+      bytes: done,
+      total,
+      rate: rate.map(|rate| rate as u64),
+    });
+  }
+
+  fn short_peer(&self) -> Option<String> {
+    self.peer.as_ref().map(|peer| peer.chars().take(PEER_WIDTH).collect())
+  }
+
+  fn figures(&self) -> (u64, Option<u64>, Option<f64>) {
+    let total = Some(self.total.load(Ordering::Relaxed)).filter(|total| *total != UNKNOWN_TOTAL);
+    let done = self.done.load(Ordering::Relaxed).min(total.unwrap_or(u64::MAX));
+    (done, total, lock(&self.rate).bytes_per_sec)
+  }
+
+  fn render(&self) -> String {
+    let (done, total, rate) = self.figures();
+    let peer = self.short_peer().map(|peer| format!(" {peer}")).unwrap_or_default();
+    let Some(total) = total else {
+      return format!(
+        "{}{peer} {:<width$} {} {}",
+        self.scope,
+        truncate(&lock(&self.name), NAME_WIDTH),
+        format_bytes(done),
+        format_rate(rate),
+        width = NAME_WIDTH,
+      );
+    };
+    let filled = if total == 0 { BAR_WIDTH } else { (done as f64 / total as f64 * BAR_WIDTH as f64) as usize };
+    format!(
+      "{}{peer} {:<width$} [{}{}] {:>3}% {} / {} {} ETA {}",
+      self.scope,
+      truncate(&lock(&self.name), NAME_WIDTH),
+      "#".repeat(filled),
+      "-".repeat(BAR_WIDTH - filled),
+      percent(done, total),
+      format_bytes(done),
+      format_bytes(total),
+      format_rate(rate),
+      format_eta(done, total, rate),
+      width = NAME_WIDTH,
+    )
+  }
+
+  fn summary(&self) -> String {
+    let (done, total, rate) = self.figures();
+    let peer = self.short_peer().map(|peer| format!("{peer} ")).unwrap_or_default();
+    let Some(total) = total else {
+      return format!("{peer}{} {}, {}", lock(&self.name), format_bytes(done), format_rate(rate));
+    };
+    format!(
+      "{peer}{} {} / {} ({}%), {}, ETA {}",
+      lock(&self.name),
+      format_bytes(done),
+      format_bytes(total),
+      percent(done, total),
+      format_rate(rate),
+      format_eta(done, total, rate),
+    )
+  }
+}
+
+fn percent(done: u64, total: u64) -> u64 {
+  (done * 100).checked_div(total).unwrap_or(100)
+}
+
+fn truncate(name: &str, width: usize) -> String {
+  let count = name.chars().count();
+  if count <= width {
+    return name.to_string();
+  }
+  let tail: String = name.chars().skip(count - (width - 1)).collect();
+  format!("…{tail}")
+}
+
+// `1 file`, `2 files`
+pub fn plural(n: usize, noun: &str) -> String {
+  if n == 1 {
+    format!("1 {noun}")
+  } else {
+    format!("{n} {noun}s")
+  }
+}
+
+pub fn format_bytes(bytes: u64) -> String {
+  const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+  let mut value = bytes as f64;
+  let mut unit = 0;
+  while value >= 1024.0 && unit < UNITS.len() - 1 {
+    value /= 1024.0;
+    unit += 1;
+  }
+  if unit == 0 {
+    format!("{bytes} B")
+  } else {
+    format!("{value:.1} {}", UNITS[unit])
+  }
+}
+
+fn format_rate(rate: Option<f64>) -> String {
+  match rate {
+    Some(rate) => format!("{}/s", format_bytes(rate as u64)),
+    None => "--/s".to_string(),
+  }
+}
+
+fn format_eta(done: u64, total: u64, rate: Option<f64>) -> String {
+  let remaining = total.saturating_sub(done);
+  if remaining == 0 {
+    return "0s".to_string();
+  }
+  let secs = match rate {
+    Some(rate) if rate >= 1.0 => (remaining as f64 / rate) as u64,
+    _ => return "--".to_string(),
+  };
+  match secs {
+    0..=59 => format!("{secs}s"),
+    60..=3599 => format!("{}m{:02}s", secs / 60, secs % 60),
+    _ => format!("{}h{:02}m", secs / 3600, secs % 3600 / 60),
+  }
+}
@@ -0,0 +1,248 @@
+// Design: files queued while `send --stay-open` runs. A prompt on stdin (`--interactive`), a local
+// control socket (`--control`) and a watched drop folder (`--watch`) all feed the same channel of
+// commands; the send loop hashes each file and hands it to every connected receiver's own queue, so
+// a slow receiver never holds up the others. Only receivers that list `push` in `hello` (a CLI
+// receiver with `--stay-open`) take files after their first transfer.
+//
+// One command per line: `send PATH` (or just `PATH`) queues a file or directory, and `status` lists
+// the receivers. The path is the rest of the line, so it may contain spaces.
+
+use anyhow::{anyhow, Context, Result};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::sync::{mpsc, oneshot};
+
+use crate::log_line;
+
+// How often the drop folder is scanned. A new file is sent once it looks the same on two scans in a
+// row, so files that are still being written are left alone.
+const WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+pub enum Request {
+  Send(PathBuf),
+  Status,
+}
+
+pub struct Command {
+  pub request: Request,
+  // What to tell whoever asked; nobody listens for the drop folder.
+  pub reply: Option<oneshot::Sender<String>>,
+}
+
+const HELP: &str = "commands: send PATH (or just PATH) to queue a file or directory for every receiver, status";
+
+fn parse(line: &str) -> std::result::Result<Request, String> {
+  let line = line.trim();
+  match line.split_once(char::is_whitespace).unwrap_or((line, "")) {
+    ("status", "") => Ok(Request::Status),
+    ("help", "") => Err(HELP.to_string()),
+    ("send", path) if !path.trim().is_empty() => Ok(Request::Send(PathBuf::from(path.trim()))),
+    ("send", _) => Err("send needs a path".to_string()),
+    _ => Ok(Request::Send(PathBuf::from(line))),
+  }
+}
+
+// Runs one line through the send loop and returns its answer.
+async fn ask(tx: &mpsc::UnboundedSender<Command>, line: &str) -> String {
+  let request = match parse(line) {
+    Ok(request) => request,
+    Err(message) => return message,
+  };
+  let (reply_tx, reply_rx) = oneshot::channel();
+  let command = Command {
+    request,
+    reply: Some(reply_tx),
+  };
+  if tx.send(command).is_err() {
+    return "the sender is shutting down".to_string();
+  }
+  reply_rx.await.unwrap_or_else(|_| "the sender is shutting down".to_string())
+}
+
+// `--interactive`: commands typed on stdin, answered on stderr with the logs.
+pub fn spawn_prompt(tx: mpsc::UnboundedSender<Command>) {
+  log_line("[push] ready", "type a path to send it to every receiver, or `status`");
+  tokio::spawn(async move {
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+      if line.trim().is_empty() {
+        continue;
+      }
+      let answer = ask(&tx, &line).await;
+      for line in answer.lines() {
+        log_line("[push] reply", line);
+      }
+    }
+  });
+}
+
+// `--control`: a Unix socket taking the same commands, one reply per command.
+#[cfg(unix)]
+pub fn spawn_control(path: &Path, tx: mpsc::UnboundedSender<Command>) -> Result<()> {
+  use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+  // A socket left behind by an earlier run would make the bind fail. It is only stale if nobody
+  // answers on it; a live one belongs to another sender.
+  if std::fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+    match std::os::unix::net::UnixStream::connect(path) {
+      Ok(_) => return Err(anyhow!("another sender is already listening on {}", path.display())),
+      Err(err) if err.kind() == std::io::ErrorKind::ConnectionRefused => {
+        let _ = std::fs::remove_file(path);
+      }
+      Err(_) => {}
+    }
+  }
+  let listener = tokio::net::UnixListener::bind(path).with_context(|| format!("listen on {}", path.display()))?;
+  // Whoever can connect can make us send any file we can read.
+  std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))
+    .with_context(|| format!("restrict {} to its owner", path.display()))?;
+  log_line("[push] control", &path.display().to_string());
+  tokio::spawn(async move {
+    while let Ok((stream, _)) = listener.accept().await {
+      let tx = tx.clone();
+      tokio::spawn(async move {
+        let (read, mut write) = stream.into_split();
+        let mut lines = BufReader::new(read).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+          if line.trim().is_empty() {
+            continue;
+          }
+          let answer = ask(&tx, &line).await;
+          if write.write_all(format!("{answer}\n").as_bytes()).await.is_err() {
+            break;
+          }
+        }
+      });
+    }
+  });
+  Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn spawn_control(_path: &Path, _tx: mpsc::UnboundedSender<Command>) -> Result<()> {
+  Err(anyhow!("--control needs a Unix socket, which this platform does not have"))
+}
+
+// `--watch`: files that show up in `dir` (not in subdirectories) are sent as they appear, and again
+// whenever they change. What is there at startup counts as already seen; hidden files and `.part`
+// files are skipped.
+pub fn spawn_watch(dir: PathBuf, tx: mpsc::UnboundedSender<Command>) -> Result<()> {
+  if !dir.is_dir() {
+    return Err(anyhow!("--watch needs a directory: {}", dir.display()));
+  }
+  log_line("[push] watching", &dir.display().to_string());
+  tokio::spawn(async move {
+    let mut watched = Watched::new(scan(&dir).await);
+    loop {
+      tokio::time::sleep(WATCH_INTERVAL).await;
+      for path in watched.settled(scan(&dir).await) {
+        let command = Command {
+          request: Request::Send(path),
+          reply: None,
+        };
+        if tx.send(command).is_err() {
+          return;
+        }
+      }
+    }
+  });
+  Ok(())
+}
+
+// Size and modification time of each file in the drop folder.
+type Scan = HashMap<PathBuf, (u64, Option<SystemTime>)>;
+
+struct Watched {
+  // What each file looked like when it was last sent.
+  sent: Scan,
+  previous: Scan,
+}
+
+impl Watched {
+  fn new(initial: Scan) -> Self {
+    Watched {
+      sent: initial.clone(),
+      previous: initial,
+    }
+  }
+
+  // The files that changed since they were last sent and look the same as on the previous scan.
+  fn settled(&mut self, current: Scan) -> Vec<PathBuf> {
+    let mut ready = Vec::new();
+    for (path, stamp) in &current {
+      if self.sent.get(path) != Some(stamp) && self.previous.get(path) == Some(stamp) {
+        self.sent.insert(path.clone(), *stamp);
+        ready.push(path.clone());
+      }
+    }
+    self.sent.retain(|path, _| current.contains_key(path));
+    self.previous = current;
+    ready
+  }
+}
+
+async fn scan(dir: &Path) -> Scan {
+  let mut found = HashMap::new();
+  let Ok(mut entries) = tokio::fs::read_dir(dir).await else {
+    return found;
+  };
+  while let Ok(Some(entry)) = entries.next_entry().await {
+    let name = entry.file_name().to_string_lossy().into_owned();
+    if name.starts_with('.') || name.ends_with(".part") {
+      continue;
+    }
+    if let Ok(metadata) = tokio::fs::metadata(entry.path()).await {
+      if metadata.is_file() {
+        found.insert(entry.path(), (metadata.len(), metadata.modified().ok()));
+      }
+    }
+  }
+  found
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn scanned(files: &[(&str, u64)]) -> Scan {
+    files
+      .iter()
+      .map(|(name, size)| (PathBuf::from(name), (*size, None)))
+      .collect()
+  }
+
+  #[test]
+  fn parse_reads_commands_and_bare_paths() {
+    assert!(matches!(parse(" status "), Ok(Request::Status)));
+    assert!(matches!(parse("send my file.txt"), Ok(Request::Send(path)) if path == Path::new("my file.txt")));
+    assert!(matches!(parse("notes/a b.txt"), Ok(Request::Send(path)) if path == Path::new("notes/a b.txt")));
+    // Only the bare word is a command.
+    assert!(matches!(parse("status report.pdf"), Ok(Request::Send(path)) if path == Path::new("status report.pdf")));
+    assert_eq!(parse("help").err().as_deref(), Some(HELP));
+    assert_eq!(parse("send  ").err().as_deref(), Some("send needs a path"));
+  }
+
+  #[test]
+  fn watched_files_go_out_once_they_stop_changing() {
+    let mut watched = Watched::new(scanned(&[("old.txt", 1)]));
+    // Still being written: seen once, then growing.
+    assert!(watched.settled(scanned(&[("old.txt", 1), ("new.txt", 10)])).is_empty());
+    assert!(watched.settled(scanned(&[("old.txt", 1), ("new.txt", 20)])).is_empty());
+    assert_eq!(watched.settled(scanned(&[("old.txt", 1), ("new.txt", 20)])), vec![PathBuf::from("new.txt")]);
+    assert!(watched.settled(scanned(&[("old.txt", 1), ("new.txt", 20)])).is_empty());
+    // A change is sent again once it settles, and so is a file that was removed and put back.
+    assert!(watched.settled(scanned(&[("old.txt", 2), ("new.txt", 20)])).is_empty());
+    assert_eq!(watched.settled(scanned(&[("old.txt", 2), ("new.txt", 20)])), vec![PathBuf::from("old.txt")]);
+    assert!(watched.settled(scanned(&[("old.txt", 2)])).is_empty());
+    assert!(watched.settled(scanned(&[("old.txt", 2), ("new.txt", 20)])).is_empty());
+    assert_eq!(watched.settled(scanned(&[("old.txt", 2), ("new.txt", 20)])), vec![PathBuf::from("new.txt")]);
+  }
+
+  #[tokio::test]
+  async fn spawn_watch_needs_a_directory() {
+    let (tx, _rx) = mpsc::unbounded_channel();
+    let file = Path::new(env!("CARGO_MANIFEST_DIR")).join("Cargo.toml");
+    assert!(spawn_watch(file, tx).is_err());
+  }
+}
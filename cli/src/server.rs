@@ -0,0 +1,792 @@
+// Design: a port of the Room Durable Object (src/room.ts) and the routes in src/index.tsx; keep the
+// two in step, since clients cannot tell which one they are talking to.
+
+use anyhow::{anyhow, Context, Result};
+use futures_util::{SinkExt, StreamExt};
+use getrandom::getrandom;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use std::io::Cursor;
+use tokio::io::{AsyncReadExt, AsyncWriteExt, Chain, Join};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, Mutex};
+use tokio_tungstenite::tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tokio_tungstenite::tungstenite::http::StatusCode;
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::protocol::CloseFrame;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::WebSocketStream;
+use url::Url;
+use uuid::Uuid;
+
+use crate::log_line;
+
+const DEFAULT_MAX_CONCURRENT: usize = 3;
+const MAX_MAX_CONCURRENT: usize = 10;
+pub const ROOM_ID_LEN: usize = 10;
+pub const ROOM_ID_ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+const MAX_HEAD_SIZE: usize = 16 * 1024;
+const MAX_BODY_SIZE: usize = 64 * 1024;
+// A created room nobody is connected to is forgotten after ROOM_IDLE_TTL, checked every
+// ROOM_SWEEP_INTERVAL. The TTL outlasts a client's reconnect attempts (about two minutes).
+const ROOM_IDLE_TTL: Duration = Duration::from_secs(10 * 60);
+const ROOM_SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+// New rooms, from POST /api/rooms or a websocket to an unknown room, are refused once this many
+// are in memory.
+const MAX_ROOMS: usize = 10_000;
+// Served at /r/<room ID>: the key is in the fragment, which only the browser sees.
+const ROOM_PAGE: &str = r#"<!doctype html>
+<meta charset="utf-8">
+<title>pairlane</title>
+<p>This server only relays signaling. Receive with the CLI:</p>
+<pre id="command"></pre>
+<script>
+  document.getElementById("command").textContent = "npx pairlane receive '" + location.href + "'";
+</script>
+"#;
+
+type Rooms = Arc<Mutex<HashMap<String, Room>>>;
+// A websocket whose request head was already read; the head is replayed to the handshake.
+type Upgraded = Join<Chain<Cursor<Vec<u8>>, OwnedReadHalf>, OwnedWriteHalf>;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Role {
+  Offerer,
+  Answerer,
+}
+
+impl Role {
+  fn as_str(self) -> &'static str {
+    match self {
+      Role::Offerer => "offerer",
+      Role::Answerer => "answerer",
+    }
+  }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum AnswererState {
+  Waiting,
+  Active,
+  Done,
+}
+
+struct RoomConfig {
+  max_concurrent: usize,
+  creator_cid: Option<String>,
+}
+
+// One open websocket; the `id` tells a replaced connection apart from its replacement (same cid).
+struct Socket {
+  id: u64,
+  cid: String,
+  role: Role,
+  state: Option<AnswererState>,
+  // Offerer only: set once the sender takes no more receivers.
+  closing: Option<String>,
+  tx: mpsc::UnboundedSender<Message>,
+}
+
+#[derive(Default)]
+struct Room {
+  config: Option<RoomConfig>,
+  // Open sockets in join order, which is also the queue order for waiting answerers.
+  sockets: Vec<Socket>,
+  // answerer cid -> offerer cid, for every answerer currently allowed to signal.
+  active_pairs: HashMap<String, String>,
+  // Since when nobody has been connected; None while someone is.
+  idle_since: Option<Instant>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type")]
+enum ClientMessage {
+  #[serde(rename = "offer")]
+  Offer { to: String, sid: u64, sdp: serde_json::Value },
+  #[serde(rename = "answer")]
+  Answer { to: String, sid: u64, sdp: serde_json::Value },
+  #[serde(rename = "candidate")]
+  Candidate { to: String, sid: u64, candidate: serde_json::Value },
+  #[serde(rename = "transfer-done")]
+  TransferDone { #[serde(rename = "peerId")] peer_id: String },
+  #[serde(rename = "peer-rejected")]
+  PeerRejected { #[serde(rename = "peerId")] peer_id: String },
+  #[serde(rename = "closing")]
+  Closing {
+    reason: String,
+    #[serde(rename = "peerId")]
+    peer_id: Option<String>,
+  },
+}
+
+struct HttpRequest {
+  method: String,
+  target: String,
+  body: Vec<u8>,
+  // Everything read from the connection so far.
+  raw: Vec<u8>,
+}
+
+pub async fn run_server(listen: SocketAddr) -> Result<()> {
+  let listener = TcpListener::bind(listen)
+    .await
+    .with_context(|| format!("listen on {listen}"))?;
+  log_line("[server] listening", &format!("http://{}", listener.local_addr()?));
+  let rooms: Rooms = Arc::new(Mutex::new(HashMap::new()));
+  tokio::spawn(sweep_rooms(rooms.clone()));
+  let next_id = Arc::new(AtomicU64::new(1));
+  loop {
+    let (stream, addr) = listener.accept().await.context("accept connection")?;
+    let rooms = rooms.clone();
+    let id = next_id.fetch_add(1, Ordering::Relaxed);
+    tokio::spawn(async move {
+      if let Err(err) = handle_connection(stream, rooms, id).await {
+        log_line("[server] error", &format!("{addr}: {err:#}"));
+      }
+    });
+  }
+}
+
+async fn handle_connection(mut stream: TcpStream, rooms: Rooms, id: u64) -> Result<()> {
+  let request = read_request(&mut stream).await?;
+  let url = Url::parse(&format!("http://localhost{}", request.target)).context("parse request target")?;
+  let segments: Vec<&str> = url.path().split('/').filter(|segment| !segment.is_empty()).collect();
+  match (request.method.as_str(), segments.as_slice()) {
+    ("POST", ["api", "rooms"]) => create_room(&mut stream, &rooms, &request.body).await,
+    ("GET", ["api", "rooms", room_id]) => room_info(&mut stream, &rooms, room_id).await,
+    ("GET", ["r", _]) => respond(&mut stream, "200 OK", "text/html; charset=utf-8", ROOM_PAGE).await,
+    ("GET", ["ws", room_id]) => {
+      let cid = url
+        .query_pairs()
+        .find(|(name, _)| name == "cid")
+        .map(|(_, value)| value.into_owned())
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
+      let full = {
+        let guard = rooms.lock().await;
+        !guard.contains_key(*room_id) && guard.len() >= MAX_ROOMS
+      };
+      let (reader, writer) = stream.into_split();
+      let stream = tokio::io::join(Cursor::new(request.raw).chain(reader), writer);
+      // The callback's error type is tungstenite's, however large.
+      #[allow(clippy::result_large_err)]
+      let ws = tokio_tungstenite::accept_hdr_async(stream, |_: &Request, response: Response| {
+        if full {
+          let mut refusal = ErrorResponse::new(Some("Too many rooms".to_string()));
+          *refusal.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+          return Err(refusal);
+        }
+        Ok(response)
+      })
+      .await
+      .context("websocket handshake")?;
+      serve_socket(ws, rooms, room_id.to_string(), cid, id).await;
+      Ok(())
+    }
+    _ => respond(&mut stream, "404 Not Found", "text/plain", "Not found").await,
+  }
+}
+
+async fn read_request(stream: &mut TcpStream) -> Result<HttpRequest> {
+  let mut buffer = Vec::new();
+  let mut chunk = [0u8; 4096];
+  let head_end = loop {
+    if let Some(pos) = buffer.windows(4).position(|window| window == b"\r\n\r\n") {
+      break pos;
+    }
+    if buffer.len() > MAX_HEAD_SIZE {
+      return Err(anyhow!("request head too large"));
+    }
+    let read = stream.read(&mut chunk).await?;
+    if read == 0 {
+      return Err(anyhow!("connection closed before request head"));
+    }
+    buffer.extend_from_slice(&chunk[..read]);
+  };
+  let head = std::str::from_utf8(&buffer[..head_end]).context("request head is not UTF-8")?;
+  let mut lines = head.split("\r\n");
+  let mut request_line = lines.next().unwrap_or_default().split(' ');
+  let method = request_line.next().unwrap_or_default().to_string();
+  let target = request_line.next().unwrap_or("/").to_string();
+  let headers: HashMap<String, String> = lines
+    .filter_map(|line| line.split_once(':'))
+    .map(|(name, value)| (name.trim().to_ascii_lowercase(), value.trim().to_string()))
+    .collect();
+  let length = headers
+    .get("content-length")
+    .and_then(|value| value.parse::<usize>().ok())
+    .unwrap_or(0);
+  if length > MAX_BODY_SIZE {
+    return Err(anyhow!("request body too large"));
+  }
+  while buffer.len() < head_end + 4 + length {
+    let read = stream.read(&mut chunk).await?;
+    if read == 0 {
+      return Err(anyhow!("connection closed before request body"));
+    }
+    buffer.extend_from_slice(&chunk[..read]);
+  }
+  let body = buffer[head_end + 4..head_end + 4 + length].to_vec();
+  Ok(HttpRequest {
+    method,
+    target,
+    body,
+    raw: buffer,
+  })
+}
+
+async fn respond(stream: &mut TcpStream, status: &str, content_type: &str, body: &str) -> Result<()> {
+  let response = format!(
+    "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+    body.len()
+  );
+  stream.write_all(response.as_bytes()).await?;
+  stream.shutdown().await?;
+  Ok(())
+}
+
+async fn create_room(stream: &mut TcpStream, rooms: &Rooms, body: &[u8]) -> Result<()> {
+  let Ok(body) = serde_json::from_slice::<serde_json::Value>(body) else {
+    return respond(stream, "400 Bad Request", "text/plain", "Invalid JSON").await;
+  };
+  let config = RoomConfig {
+    max_concurrent: normalize_max_concurrent(body.get("maxConcurrent").and_then(|value| value.as_f64())),
+    creator_cid: body.get("creatorCid").and_then(|value| value.as_str()).map(str::to_string),
+  };
+  let room_id = generate_room_id()?;
+  let max_concurrent = config.max_concurrent;
+  {
+    let mut guard = rooms.lock().await;
+    if guard.len() >= MAX_ROOMS {
+      drop(guard);
+      return respond(stream, "503 Service Unavailable", "text/plain", "Too many rooms").await;
+    }
+    let room = guard.entry(room_id.clone()).or_default();
+    room.config = Some(config);
+    room.idle_since = Some(Instant::now());
+  }
+  log_line("[server] room created", &format!("{room_id} (maxConcurrent {max_concurrent})"));
+  let body = serde_json::json!({ "roomId": room_id }).to_string();
+  respond(stream, "200 OK", "application/json", &body).await
+}
+
+// Only the settings: who is connected is the room's business, and the creator's client ID would let
+// whoever presents it become the room's sender.
+async fn room_info(stream: &mut TcpStream, rooms: &Rooms, room_id: &str) -> Result<()> {
+  let max_concurrent = match rooms.lock().await.get(room_id) {
+    Some(room) => room
+      .config
+      .as_ref()
+      .map(|config| config.max_concurrent)
+      .unwrap_or(DEFAULT_MAX_CONCURRENT),
+    None => return respond(stream, "404 Not Found", "text/plain", "Room not found").await,
+  };
+  let body = serde_json::json!({ "roomId": room_id, "maxConcurrent": max_concurrent }).to_string();
+  respond(stream, "200 OK", "application/json", &body).await
+}
+
+fn normalize_max_concurrent(value: Option<f64>) -> usize {
+  let base = value.filter(|value| value.is_finite()).map(f64::floor).unwrap_or(DEFAULT_MAX_CONCURRENT as f64);
+  base.clamp(1.0, MAX_MAX_CONCURRENT as f64) as usize
+}
+
+fn generate_room_id() -> Result<String> {
+  let mut bytes = [0u8; ROOM_ID_LEN];
+  getrandom(&mut bytes).map_err(|err| anyhow!(err))?;
+  Ok(
+    bytes
+      .iter()
+      .map(|byte| ROOM_ID_ALPHABET[*byte as usize % ROOM_ID_ALPHABET.len()] as char)
+      .collect(),
+  )
+}
+
+async fn serve_socket(ws: WebSocketStream<Upgraded>, rooms: Rooms, room_id: String, cid: String, id: u64) {
+  let (mut ws_write, mut ws_read) = ws.split();
+  let (tx, mut rx) = mpsc::unbounded_channel::<Message>();
+  tokio::spawn(async move {
+    while let Some(message) = rx.recv().await {
+      let closing = matches!(message, Message::Close(_));
+      if ws_write.send(message).await.is_err() || closing {
+        break;
+      }
+    }
+  });
+
+  let role = {
+    let mut guard = rooms.lock().await;
+    let room = guard.entry(room_id.clone()).or_default();
+    room.idle_since = None;
+    room.close_duplicate_client(&cid);
+    let role = room.pick_role(&cid);
+    log_line("[server] join", &format!("{room_id}: {cid} as {}", role.as_str()));
+    // An answerer arriving after the sender stopped taking receivers is told so instead of queued.
+    let closing = match role {
+      Role::Answerer => room.offerer().and_then(|offerer| offerer.closing.clone()),
+      Role::Offerer => None,
+    };
+    let socket = Socket {
+      id,
+      cid: cid.clone(),
+      role,
+      state: (role == Role::Answerer).then_some(match closing {
+        Some(_) => AnswererState::Done,
+        None => AnswererState::Waiting,
+      }),
+      closing: None,
+      tx,
+    };
+    send_json(&socket, serde_json::json!({ "type": "role", "role": role.as_str(), "cid": cid }));
+    match closing {
+      Some(reason) => send_json(&socket, serde_json::json!({ "type": "closing", "reason": reason })),
+      None if role == Role::Answerer => send_json(&socket, serde_json::json!({ "type": "wait" })),
+      None => {}
+    }
+    room.sockets.push(socket);
+    room.broadcast_peers();
+    room.fill_slots();
+    role
+  };
+
+  while let Some(Ok(message)) = ws_read.next().await {
+    let text = match message {
+      Message::Text(text) => text,
+      Message::Binary(data) => String::from_utf8_lossy(&data).into_owned(),
+      Message::Close(_) => break,
+      _ => continue,
+    };
+    let Ok(message) = serde_json::from_str::<ClientMessage>(&text) else {
+      continue;
+    };
+    let mut guard = rooms.lock().await;
+    if let Some(room) = guard.get_mut(&room_id) {
+      room.handle_message(&cid, role, message);
+    }
+  }
+
+  let mut guard = rooms.lock().await;
+  if let Some(room) = guard.get_mut(&room_id) {
+    log_line("[server] leave", &format!("{room_id}: {cid} ({})", role.as_str()));
+    room.handle_close(id, &cid, role);
+    if room.sockets.is_empty() {
+      match room.config {
+        Some(_) => room.idle_since = Some(Instant::now()),
+        None => {
+          guard.remove(&room_id);
+        }
+      }
+    }
+  }
+}
+
+// Forgets created rooms that have been empty for ROOM_IDLE_TTL; the Durable Object's storage has
+// no such limit, but this server keeps every room in memory.
+async fn sweep_rooms(rooms: Rooms) {
+  let mut interval = tokio::time::interval(ROOM_SWEEP_INTERVAL);
+  loop {
+    interval.tick().await;
+    rooms.lock().await.retain(|room_id, room| {
+      let expired = room.idle_since.is_some_and(|since| since.elapsed() >= ROOM_IDLE_TTL);
+      if expired {
+        log_line("[server] room expired", room_id);
+      }
+      !expired
+    });
+  }
+}
+
+fn send_json(socket: &Socket, payload: serde_json::Value) {
+  let _ = socket.tx.send(Message::Text(payload.to_string()));
+}
+
+impl Room {
+  fn handle_message(&mut self, cid: &str, role: Role, message: ClientMessage) {
+    let (to, payload) = match message {
+      ClientMessage::TransferDone { peer_id } | ClientMessage::PeerRejected { peer_id } => {
+        if role != Role::Offerer {
+          return;
+        }
+        if let Some(socket) = self.sockets.iter_mut().find(|socket| socket.cid == peer_id) {
+          socket.state = Some(AnswererState::Done);
+        }
+        self.fill_slots();
+        return;
+      }
+      // Everyone still queued (and `peer_id`, a receiver the sender was started with but turns
+      // away) is told why and never started.
+      ClientMessage::Closing { reason, peer_id } => {
+        if role != Role::Offerer {
+          return;
+        }
+        if let Some(offerer) = self.sockets.iter_mut().find(|socket| socket.cid == cid && socket.role == Role::Offerer) {
+          offerer.closing = Some(reason.clone());
+        }
+        for socket in self.sockets.iter_mut().filter(|socket| socket.role == Role::Answerer) {
+          if socket.state != Some(AnswererState::Waiting) && Some(&socket.cid) != peer_id.as_ref() {
+            continue;
+          }
+          socket.state = Some(AnswererState::Done);
+          send_json(socket, serde_json::json!({ "type": "closing", "reason": reason }));
+        }
+        return;
+      }
+      ClientMessage::Offer { to, sid, sdp } => {
+        if role != Role::Offerer || self.active_pairs.get(&to).map(String::as_str) != Some(cid) {
+          return;
+        }
+        let payload = serde_json::json!({ "type": "offer", "from": cid, "sid": sid, "sdp": sdp });
+        (to, payload)
+      }
+      ClientMessage::Answer { to, sid, sdp } => {
+        if role != Role::Answerer || self.active_pairs.get(cid) != Some(&to) {
+          return;
+        }
+        let payload = serde_json::json!({ "type": "answer", "from": cid, "sid": sid, "sdp": sdp });
+        (to, payload)
+      }
+      ClientMessage::Candidate { to, sid, candidate } => {
+        let allowed = match role {
+          Role::Offerer => self.active_pairs.get(&to).map(String::as_str) == Some(cid),
+          Role::Answerer => self.active_pairs.get(cid) == Some(&to),
+        };
+        if !allowed {
+          return;
+        }
+        let payload = serde_json::json!({ "type": "candidate", "from": cid, "sid": sid, "candidate": candidate });
+        (to, payload)
+      }
+    };
+    if let Some(target) = self.sockets.iter().find(|socket| socket.cid == to) {
+      send_json(target, payload);
+    }
+  }
+
+  fn handle_close(&mut self, id: u64, cid: &str, role: Role) {
+    self.sockets.retain(|socket| socket.id != id);
+    let has_replacement = self.sockets.iter().any(|socket| socket.cid == cid && socket.role == role);
+    if has_replacement {
+      self.broadcast_peers();
+      return;
+    }
+    match role {
+      Role::Answerer => {
+        self.active_pairs.remove(cid);
+        if let Some(offerer) = self.offerer() {
+          send_json(offerer, serde_json::json!({ "type": "peer-left", "peerId": cid }));
+        }
+        self.fill_slots();
+      }
+      Role::Offerer => {
+        self.active_pairs.clear();
+        for socket in self.sockets.iter_mut().filter(|socket| socket.role == Role::Answerer) {
+          socket.state = Some(AnswererState::Waiting);
+          send_json(socket, serde_json::json!({ "type": "wait" }));
+        }
+      }
+    }
+    self.broadcast_peers();
+  }
+
+  fn pick_role(&self, cid: &str) -> Role {
+    if let Some(creator_cid) = self.config.as_ref().and_then(|config| config.creator_cid.as_deref()) {
+      return if creator_cid == cid { Role::Offerer } else { Role::Answerer };
+    }
+    if self.offerer().is_none() {
+      Role::Offerer
+    } else {
+      Role::Answerer
+    }
+  }
+
+  fn offerer(&self) -> Option<&Socket> {
+    self.sockets.iter().find(|socket| socket.role == Role::Offerer)
+  }
+
+  fn broadcast_peers(&self) {
+    let payload = serde_json::json!({ "type": "peers", "count": self.sockets.len() });
+    for socket in &self.sockets {
+      send_json(socket, payload.clone());
+    }
+  }
+
+  fn fill_slots(&mut self) {
+    let Some(offerer) = self.offerer() else {
+      return;
+    };
+    let offerer_cid = offerer.cid.clone();
+    let offerer_tx = offerer.tx.clone();
+    let max_concurrent = self
+      .config
+      .as_ref()
+      .map(|config| config.max_concurrent)
+      .unwrap_or(DEFAULT_MAX_CONCURRENT);
+    let active = self
+      .sockets
+      .iter()
+      .filter(|socket| socket.state == Some(AnswererState::Active))
+      .count();
+    let available = max_concurrent.saturating_sub(active);
+    let waiting = self
+      .sockets
+      .iter_mut()
+      .filter(|socket| socket.state == Some(AnswererState::Waiting))
+      .take(available);
+    for socket in waiting {
+      socket.state = Some(AnswererState::Active);
+      self.active_pairs.insert(socket.cid.clone(), offerer_cid.clone());
+      send_json(socket, serde_json::json!({ "type": "start" }));
+      let start = serde_json::json!({ "type": "start", "peerId": socket.cid });
+      let _ = offerer_tx.send(Message::Text(start.to_string()));
+    }
+  }
+
+  // A reconnect with the same cid replaces the old socket, as in the Durable Object.
+  fn close_duplicate_client(&mut self, cid: &str) {
+    self.sockets.retain(|socket| {
+      if socket.cid != cid {
+        return true;
+      }
+      log_line("[server] closing duplicate client", &format!("{cid} ({})", socket.role.as_str()));
+      let _ = socket.tx.send(Message::Close(Some(CloseFrame {
+        code: CloseCode::Normal,
+        reason: "replaced".into(),
+      })));
+      false
+    });
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn room(max_concurrent: usize) -> Room {
+    Room {
+      config: Some(RoomConfig {
+        max_concurrent,
+        creator_cid: None,
+      }),
+      ..Room::default()
+    }
+  }
+
+  fn join(room: &mut Room, id: u64, cid: &str, role: Role) -> mpsc::UnboundedReceiver<Message> {
+    let (tx, rx) = mpsc::unbounded_channel();
+    room.sockets.push(Socket {
+      id,
+      cid: cid.to_string(),
+      role,
+      state: (role == Role::Answerer).then_some(AnswererState::Waiting),
+      closing: None,
+      tx,
+    });
+    room.fill_slots();
+    rx
+  }
+
+  // Message types received so far, with the peer ID for `start` and `peer-left`.
+  fn received(rx: &mut mpsc::UnboundedReceiver<Message>) -> Vec<String> {
+    let mut seen = Vec::new();
+    while let Ok(Message::Text(text)) = rx.try_recv() {
+      let value: serde_json::Value = serde_json::from_str(&text).unwrap();
+      let kind = value["type"].as_str().unwrap().to_string();
+      match value["peerId"].as_str() {
+        Some(peer_id) => seen.push(format!("{kind} {peer_id}")),
+        None => seen.push(kind),
+      }
+    }
+    seen
+  }
+
+  fn done(cid: &str) -> ClientMessage {
+    ClientMessage::TransferDone { peer_id: cid.to_string() }
+  }
+
+  // Sends one HTTP request through `handle_connection`; returns the status line and the body.
+  async fn request(rooms: &Rooms, method: &str, target: &str, body: &str) -> (String, String) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let mut client = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+    let (server, _) = listener.accept().await.unwrap();
+    let head = format!("{method} {target} HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n", body.len());
+    client.write_all(format!("{head}{body}").as_bytes()).await.unwrap();
+    handle_connection(server, rooms.clone(), 1).await.unwrap();
+    let mut response = String::new();
+    client.read_to_string(&mut response).await.unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    (head.lines().next().unwrap().to_string(), body.to_string())
+  }
+
+  #[tokio::test]
+  async fn create_room_takes_the_options() {
+    let rooms: Rooms = Arc::default();
+    let (status, body) = request(&rooms, "POST", "/api/rooms", r#"{"maxConcurrent":50,"creatorCid":"me"}"#).await;
+    assert_eq!(status, "HTTP/1.1 200 OK");
+    let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+    let room_id = body["roomId"].as_str().unwrap();
+    let guard = rooms.lock().await;
+    let config = guard[room_id].config.as_ref().unwrap();
+    assert_eq!((config.max_concurrent, config.creator_cid.as_deref()), (MAX_MAX_CONCURRENT, Some("me")));
+    drop(guard);
+    let (status, _) = request(&rooms, "POST", "/api/rooms", "{").await;
+    assert_eq!(status, "HTTP/1.1 400 Bad Request");
+  }
+
+  #[tokio::test]
+  async fn room_info_shows_only_the_settings_of_existing_rooms() {
+    let rooms: Rooms = Arc::default();
+    let (_, body) = request(&rooms, "POST", "/api/rooms", r#"{"maxConcurrent":5,"creatorCid":"me"}"#).await;
+    let room_id = serde_json::from_str::<serde_json::Value>(&body).unwrap()["roomId"].as_str().unwrap().to_string();
+    let _offerer = join(rooms.lock().await.get_mut(&room_id).unwrap(), 1, "me", Role::Offerer);
+    let (status, body) = request(&rooms, "GET", &format!("/api/rooms/{room_id}"), "").await;
+    assert_eq!(status, "HTTP/1.1 200 OK");
+    let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(body, serde_json::json!({ "roomId": room_id, "maxConcurrent": 5 }));
+    let (status, _) = request(&rooms, "GET", "/api/rooms/NEVERMADE1", "").await;
+    assert_eq!(status, "HTTP/1.1 404 Not Found");
+    assert!(!rooms.lock().await.contains_key("NEVERMADE1"));
+  }
+
+  #[test]
+  fn slots_go_to_the_queue_in_join_order() {
+    let mut room = room(2);
+    let mut offerer = join(&mut room, 1, "s", Role::Offerer);
+    let mut first = join(&mut room, 2, "a", Role::Answerer);
+    let mut second = join(&mut room, 3, "b", Role::Answerer);
+    let mut third = join(&mut room, 4, "c", Role::Answerer);
+    assert_eq!(received(&mut offerer), ["start a", "start b"]);
+    assert_eq!(received(&mut first), ["start"]);
+    assert_eq!(received(&mut second), ["start"]);
+    assert!(received(&mut third).is_empty());
+    assert_eq!(room.active_pairs.len(), 2);
+  }
+
+  #[test]
+  fn answerers_wait_until_a_sender_joins() {
+    let mut room = room(3);
+    let mut receiver = join(&mut room, 1, "a", Role::Answerer);
+    assert!(received(&mut receiver).is_empty());
+    let mut offerer = join(&mut room, 2, "s", Role::Offerer);
+    assert_eq!(received(&mut offerer), ["start a"]);
+    assert_eq!(received(&mut receiver), ["start"]);
+  }
+
+  #[test]
+  fn a_finished_or_departed_receiver_frees_its_slot() {
+    let mut room = room(1);
+    let mut offerer = join(&mut room, 1, "s", Role::Offerer);
+    join(&mut room, 2, "a", Role::Answerer);
+    let mut second = join(&mut room, 3, "b", Role::Answerer);
+    let mut third = join(&mut room, 4, "c", Role::Answerer);
+    assert_eq!(received(&mut offerer), ["start a"]);
+
+    room.handle_message("s", Role::Offerer, done("a"));
+    assert_eq!(received(&mut offerer), ["start b"]);
+    assert_eq!(received(&mut second), ["start"]);
+    // Only the sender can end a transfer.
+    room.handle_message("b", Role::Answerer, done("b"));
+    assert!(received(&mut third).is_empty());
+
+    room.handle_close(3, "b", Role::Answerer);
+    assert_eq!(received(&mut offerer), ["peer-left b", "start c", "peers"]);
+    assert_eq!(received(&mut third), ["start", "peers"]);
+    assert!(!room.active_pairs.contains_key("b"));
+  }
+
+  #[test]
+  fn a_departed_sender_sends_everyone_back_to_the_queue() {
+    let mut room = room(3);
+    join(&mut room, 1, "s", Role::Offerer);
+    let mut receiver = join(&mut room, 2, "a", Role::Answerer);
+    received(&mut receiver);
+    room.handle_close(1, "s", Role::Offerer);
+    assert_eq!(received(&mut receiver), ["wait", "peers"]);
+    assert!(room.active_pairs.is_empty());
+
+    let mut offerer = join(&mut room, 3, "s2", Role::Offerer);
+    assert_eq!(received(&mut offerer), ["start a"]);
+  }
+
+  #[test]
+  fn a_replaced_socket_keeps_its_slot() {
+    let mut room = room(1);
+    let mut offerer = join(&mut room, 1, "s", Role::Offerer);
+    join(&mut room, 2, "a", Role::Answerer);
+    received(&mut offerer);
+    let (tx, _rx) = mpsc::unbounded_channel();
+    room.sockets.push(Socket {
+      id: 3,
+      cid: "a".to_string(),
+      role: Role::Answerer,
+      state: Some(AnswererState::Active),
+      closing: None,
+      tx,
+    });
+    room.handle_close(2, "a", Role::Answerer);
+    assert_eq!(received(&mut offerer), ["peers"]);
+    assert!(room.active_pairs.contains_key("a"));
+  }
+
+  #[test]
+  fn closing_turns_away_the_queue_but_not_active_transfers() {
+    let mut room = room(1);
+    join(&mut room, 1, "s", Role::Offerer);
+    let mut active = join(&mut room, 2, "a", Role::Answerer);
+    let mut queued = join(&mut room, 3, "b", Role::Answerer);
+    received(&mut active);
+    let closing = ClientMessage::Closing {
+      reason: "done".to_string(),
+      peer_id: None,
+    };
+    room.handle_message("s", Role::Offerer, closing);
+    assert!(received(&mut active).is_empty());
+    assert_eq!(received(&mut queued), ["closing"]);
+    // The freed slot is not handed to anyone turned away.
+    room.handle_message("s", Role::Offerer, done("a"));
+    assert!(received(&mut queued).is_empty());
+  }
+
+  #[test]
+  fn signaling_only_flows_between_paired_peers() {
+    let mut room = room(1);
+    join(&mut room, 1, "s", Role::Offerer);
+    let mut active = join(&mut room, 2, "a", Role::Answerer);
+    let mut queued = join(&mut room, 3, "b", Role::Answerer);
+    received(&mut active);
+    let offer = |to: &str| ClientMessage::Offer {
+      to: to.to_string(),
+      sid: 1,
+      sdp: serde_json::json!({}),
+    };
+    room.handle_message("s", Role::Offerer, offer("b"));
+    assert!(received(&mut queued).is_empty());
+    room.handle_message("b", Role::Offerer, offer("a"));
+    assert!(received(&mut active).is_empty());
+    room.handle_message("s", Role::Offerer, offer("a"));
+    assert_eq!(received(&mut active), ["offer"]);
+  }
+
+  #[test]
+  fn only_the_creator_becomes_the_sender() {
+    let mut room = room(3);
+    room.config.as_mut().unwrap().creator_cid = Some("creator".to_string());
+    assert_eq!(room.pick_role("someone"), Role::Answerer);
+    assert_eq!(room.pick_role("creator"), Role::Offerer);
+    let mut open = Room::default();
+    assert_eq!(open.pick_role("first"), Role::Offerer);
+    join(&mut open, 1, "first", Role::Offerer);
+    assert_eq!(open.pick_role("second"), Role::Answerer);
+  }
+
+  #[test]
+  fn max_concurrent_is_clamped_like_the_worker() {
+    assert_eq!(normalize_max_concurrent(None), DEFAULT_MAX_CONCURRENT);
+    assert_eq!(normalize_max_concurrent(Some(f64::NAN)), DEFAULT_MAX_CONCURRENT);
+    assert_eq!(normalize_max_concurrent(Some(0.0)), 1);
+    assert_eq!(normalize_max_concurrent(Some(2.9)), 2);
+    assert_eq!(normalize_max_concurrent(Some(500.0)), MAX_MAX_CONCURRENT);
+  }
+}
@@ -0,0 +1,256 @@
+// Design: when a `send --stay-open` sender stops. `--max-receivers` caps how many receivers it starts
+// a transfer with, `--timeout`/`--until` set a deadline, and `--idle-timeout` ends a session nobody
+// is using. Once one of them is reached the sender tells the signaling server it is `closing`, so
+// receivers still queued (or arriving later) hear why and are never started. It then lets the
+// transfers in flight finish, closes the connections that are idle and the websocket, and logs what
+// each receiver got.
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Local, NaiveTime, TimeZone};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio::sync::Notify;
+use tokio::time::Instant;
+
+use crate::progress::plural;
+
+#[derive(Default)]
+pub struct Limits {
+  pub max_receivers: Option<usize>,
+  pub deadline: Option<DateTime<Local>>,
+  pub idle_timeout: Option<Duration>,
+}
+
+impl Limits {
+  // For the log, e.g. "25 receivers, until 2026-03-01 17:00:00"; None when nothing is set.
+  pub fn describe(&self) -> Option<String> {
+    let mut parts = Vec::new();
+    if let Some(count) = self.max_receivers {
+      parts.push(plural(count, "receiver"));
+    }
+    if let Some(deadline) = self.deadline {
+      parts.push(format!("until {}", deadline.format("%Y-%m-%d %H:%M:%S")));
+    }
+    if let Some(idle_timeout) = self.idle_timeout {
+      parts.push(format!("{} idle", format_duration(idle_timeout)));
+    }
+    (!parts.is_empty()).then(|| parts.join(", "))
+  }
+
+  // The first of the deadline and the end of the idle period that started at `last_activity`.
+  pub fn next_stop(&self, last_activity: Instant) -> Option<(Instant, Stop)> {
+    let idle = self
+      .idle_timeout
+      .map(|idle_timeout| (last_activity + idle_timeout, Stop::Idle(idle_timeout)));
+    let deadline = self.deadline.map(|deadline| (instant_at(deadline), Stop::Deadline));
+    match (deadline, idle) {
+      (Some(deadline), Some(idle)) => Some(if idle.0 < deadline.0 { idle } else { deadline }),
+      (deadline, idle) => deadline.or(idle),
+    }
+  }
+}
+
+// `--max-receivers` counts receivers that passed the key check, so a stranger with only the room
+// ID cannot use up the slots. Taken from the peers' own tasks; the send loop waits on `changed`.
+#[derive(Default)]
+pub struct Admissions {
+  max: Option<usize>,
+  count: AtomicUsize,
+  changed: Notify,
+}
+
+impl Admissions {
+  pub fn new(max: Option<usize>) -> Self {
+    Admissions {
+      max,
+      ..Default::default()
+    }
+  }
+
+  // Takes a slot for a receiver that proved it holds the key; false once they are all taken.
+  pub fn admit(&self) -> bool {
+    let admitted = self
+      .count
+      .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |count| match self.max {
+        Some(max) if count >= max => None,
+        _ => Some(count + 1),
+      })
+      .is_ok();
+    if admitted {
+      self.changed.notify_one();
+    }
+    admitted
+  }
+
+  // The stop once every slot is taken.
+  pub fn full(&self) -> Option<Stop> {
+    let max = self.max?;
+    (self.count.load(Ordering::SeqCst) >= max).then_some(Stop::MaxReceivers(max))
+  }
+
+  pub async fn changed(&self) {
+    self.changed.notified().await
+  }
+}
+
+#[derive(Clone, Copy)]
+pub enum Stop {
+  MaxReceivers(usize),
+  Deadline,
+  Idle(Duration),
+}
+
+impl Stop {
+  // Told to the receivers that are turned away, so it reads after "the sender is not taking more
+  // receivers: ".
+  pub fn reason(self) -> String {
+    match self {
+      Stop::MaxReceivers(1) => "it has served its receiver".to_string(),
+      Stop::MaxReceivers(count) => format!("it has served its {count} receivers"),
+      Stop::Deadline => "its time is up".to_string(),
+      Stop::Idle(idle) => format!("nothing was sent for {}", format_duration(idle)),
+    }
+  }
+}
+
+// `90s`, `45m`, `2h`, `1h30m`, `1d`; a bare number is seconds.
+pub fn parse_duration(value: &str) -> std::result::Result<Duration, String> {
+  let invalid = || format!("`{value}` is not a duration (e.g. 90s, 45m, 2h, 1h30m)");
+  let mut rest = value.trim();
+  if rest.is_empty() {
+    return Err(invalid());
+  }
+  let mut seconds = 0u64;
+  while !rest.is_empty() {
+    let digits = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+    let (number, tail) = rest.split_at(digits);
+    let number: u64 = number.parse().map_err(|_| invalid())?;
+    let letters = tail.find(|c: char| c.is_ascii_digit()).unwrap_or(tail.len());
+    let (unit, tail) = tail.split_at(letters);
+    let scale = match unit.trim() {
+      "" | "s" => 1,
+      "m" => 60,
+      "h" => 60 * 60,
+      "d" => 24 * 60 * 60,
+      _ => return Err(invalid()),
+    };
+    seconds = seconds.saturating_add(number.saturating_mul(scale));
+    rest = tail;
+  }
+  if seconds == 0 {
+    return Err(format!("`{value}` must be longer than zero"));
+  }
+  Ok(Duration::from_secs(seconds))
+}
+
+// `17:00` or `17:00:30` is the next time the clock shows it, today or tomorrow; anything else must
+// be an RFC 3339 date and time such as `2026-03-01T17:00:00+09:00`.
+pub fn parse_until(value: &str) -> std::result::Result<DateTime<Local>, String> {
+  let now = Local::now();
+  let value = value.trim();
+  if let Ok(time) = NaiveTime::parse_from_str(value, "%H:%M").or_else(|_| NaiveTime::parse_from_str(value, "%H:%M:%S")) {
+    let mut date = now.date_naive();
+    loop {
+      let at = Local
+        .from_local_datetime(&date.and_time(time))
+        .earliest()
+        .ok_or_else(|| format!("{value} does not exist on {date} in the local time zone"))?;
+      if at > now {
+        return Ok(at);
+      }
+      date = date.succ_opt().ok_or_else(|| format!("`{value}` is out of range"))?;
+    }
+  }
+  let at = DateTime::parse_from_rfc3339(value)
+    .map_err(|_| format!("`{value}` is not a time (use HH:MM or an RFC 3339 date and time)"))?
+    .with_timezone(&Local);
+  if at <= now {
+    return Err(format!("{value} has already passed"));
+  }
+  Ok(at)
+}
+
+// `--timeout`: the wall-clock time `duration` from now.
+pub fn deadline_after(duration: Duration) -> Result<DateTime<Local>> {
+  chrono::Duration::from_std(duration)
+    .ok()
+    .and_then(|duration| Local::now().checked_add_signed(duration))
+    .ok_or_else(|| anyhow!("--timeout is too long"))
+}
+
+// The monotonic clock's reading at wall-clock time `at`.
+fn instant_at(at: DateTime<Local>) -> Instant {
+  Instant::now() + (at - Local::now()).to_std().unwrap_or_default()
+}
+
+pub fn format_duration(duration: Duration) -> String {
+  let secs = duration.as_secs();
+  match (secs / 3600, secs % 3600 / 60, secs % 60) {
+    (0, 0, secs) => format!("{secs}s"),
+    (0, minutes, 0) => format!("{minutes}m"),
+    (0, minutes, secs) => format!("{minutes}m{secs:02}s"),
+    (hours, 0, 0) => format!("{hours}h"),
+    (hours, minutes, _) => format!("{hours}h{minutes:02}m"),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use chrono::Timelike;
+
+  #[test]
+  fn parse_duration_adds_up_units() {
+    assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+    assert_eq!(parse_duration("90s"), Ok(Duration::from_secs(90)));
+    assert_eq!(parse_duration("45m"), Ok(Duration::from_secs(45 * 60)));
+    assert_eq!(parse_duration(" 1h30m "), Ok(Duration::from_secs(90 * 60)));
+    assert_eq!(parse_duration("1h 30m"), Ok(Duration::from_secs(90 * 60)));
+    assert_eq!(parse_duration("1d"), Ok(Duration::from_secs(24 * 60 * 60)));
+  }
+
+  #[test]
+  fn parse_duration_rejects_nonsense_and_zero() {
+    for value in ["", "h", "1x", "1.5h", "-1m", "1 hour", "0", "0s0m"] {
+      assert!(parse_duration(value).is_err(), "{value}");
+    }
+  }
+
+  #[test]
+  fn parse_until_takes_the_next_clock_time() {
+    let now = Local::now();
+    let at = parse_until("17:00").unwrap();
+    assert!(at > now && at - now <= chrono::Duration::hours(25));
+    assert_eq!((at.hour(), at.minute(), at.second()), (17, 0, 0));
+    assert_eq!(parse_until("06:30:15").unwrap().second(), 15);
+  }
+
+  #[test]
+  fn parse_until_takes_future_rfc3339_only() {
+    assert!(parse_until("2999-01-01T00:00:00Z").is_ok());
+    for value in ["2000-01-01T00:00:00+09:00", "25:00", "tomorrow", "2999-01-01"] {
+      assert!(parse_until(value).is_err(), "{value}");
+    }
+  }
+
+  #[test]
+  fn format_duration_reads_back() {
+    for secs in [5, 60, 90, 3600, 5400, 86400] {
+      let duration = Duration::from_secs(secs);
+      assert_eq!(parse_duration(&format_duration(duration)), Ok(duration), "{secs}");
+    }
+  }
+
+  #[test]
+  fn admissions_stop_at_the_limit() {
+    let admissions = Admissions::new(Some(2));
+    assert!(admissions.full().is_none());
+    assert!(admissions.admit());
+    assert!(admissions.admit());
+    assert!(!admissions.admit());
+    assert!(matches!(admissions.full(), Some(Stop::MaxReceivers(2))));
+    let unlimited = Admissions::new(None);
+    assert!((0..100).all(|_| unlimited.admit()));
+    assert!(unlimited.full().is_none());
+  }
+}
@@ -0,0 +1,264 @@
+// Design: the signaling websocket of `send` and `receive`, reconnected with the same client ID when it
+// drops. A room that does not remember its sender may hand the role to whoever shows up first, so a
+// reconnect that comes back with the other role counts as a failed attempt.
+
+use anyhow::{anyhow, Result};
+use futures_util::{SinkExt, StreamExt};
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+use tokio_tungstenite::connect_async;
+use tokio_tungstenite::tungstenite::Message;
+use url::Url;
+
+use crate::error::ErrorKind;
+use crate::events::{self, Event};
+use crate::{log_line, ClientMessage, ServerMessage, SignalingStream};
+
+// The first retry waits RECONNECT_DELAY, each failed one doubles it up to RECONNECT_DELAY_MAX, and
+// after RECONNECT_ATTEMPTS failures in a row the server counts as gone (about two minutes).
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const RECONNECT_DELAY_MAX: Duration = Duration::from_secs(30);
+const RECONNECT_ATTEMPTS: u32 = 8;
+
+// The close reason the server gives a socket when another one connects with the same client ID.
+const REPLACED: &str = "replaced";
+
+pub struct Signaling {
+  incoming: mpsc::UnboundedReceiver<String>,
+  close: Option<oneshot::Sender<()>>,
+  task: JoinHandle<Result<()>>,
+}
+
+// Takes over `stream`, already connected to `url` with `role`, and writes whatever arrives on
+// `outgoing`.
+pub fn spawn(
+  url: Url,
+  role: &'static str,
+  stream: SignalingStream,
+  outgoing: mpsc::UnboundedReceiver<ClientMessage>,
+) -> Signaling {
+  let (incoming_tx, incoming) = mpsc::unbounded_channel();
+  let (close, close_rx) = oneshot::channel();
+  let task = tokio::spawn(run(url, role, stream, outgoing, incoming_tx, close_rx));
+  Signaling {
+    incoming,
+    close: Some(close),
+    task,
+  }
+}
+
+impl Signaling {
+  // The next text message from the server; None once signaling is gone for good.
+  pub async fn recv(&mut self) -> Option<String> {
+    self.incoming.recv().await
+  }
+
+  // Sends what is still queued and closes the websocket.
+  pub async fn close(mut self) {
+    if let Some(close) = self.close.take() {
+      let _ = close.send(());
+    }
+    let _ = self.task.await;
+  }
+
+  // Drops the websocket without waiting for anything queued.
+  pub fn abort(self) {
+    self.task.abort();
+  }
+
+  // Why signaling is gone, once `recv` has returned None.
+  pub async fn error(self) -> anyhow::Error {
+    match self.task.await {
+      Ok(Err(err)) => err,
+      _ => ErrorKind::Signaling.error("signaling server closed the connection"),
+    }
+  }
+}
+
+async fn run(
+  url: Url,
+  role: &'static str,
+  stream: SignalingStream,
+  mut outgoing: mpsc::UnboundedReceiver<ClientMessage>,
+  incoming: mpsc::UnboundedSender<String>,
+  mut close_rx: oneshot::Receiver<()>,
+) -> Result<()> {
+  let mut stream = stream;
+  // The next message to write. One the dropped connection failed to write goes out first on the next.
+  let mut unsent: Option<String> = None;
+  loop {
+    let (mut write, mut read) = stream.split();
+    let dropped = loop {
+      if let Some(text) = unsent.take() {
+        if let Err(err) = write.send(Message::Text(text.clone())).await {
+          unsent = Some(text);
+          break err.to_string();
+        }
+      }
+      tokio::select! {
+        // Whatever was queued before the close still goes out.
+        biased;
+        msg = outgoing.recv() => {
+          let Some(msg) = msg else {
+            let _ = write.close().await;
+            return Ok(());
+          };
+          unsent = Some(serde_json::to_string(&msg).map_err(|err| anyhow!(err))?);
+        }
+        _ = &mut close_rx => {
+          let _ = write.close().await;
+          return Ok(());
+        }
+        msg = read.next() => match msg {
+          Some(Ok(Message::Text(text))) => {
+            if incoming.send(text).is_err() {
+              return Ok(());
+            }
+          }
+          Some(Ok(Message::Close(Some(frame)))) if frame.reason == REPLACED => {
+            return Err(ErrorKind::Signaling.error("another client with the same ID connected to the room"));
+          }
+          Some(Ok(Message::Close(frame))) => {
+            break match frame {
+              Some(frame) => format!("closed by the server ({})", frame.code),
+              None => "closed by the server".to_string(),
+            };
+          }
+          Some(Ok(_)) => {}
+          Some(Err(err)) => break err.to_string(),
+          None => break "connection lost".to_string(),
+        }
+      }
+    };
+    log_line("[ws] disconnected", &dropped);
+    let greeting;
+    (stream, greeting) = match reconnect(&url, role, &mut close_rx).await? {
+      Some(reconnected) => reconnected,
+      None => return Ok(()),
+    };
+    if incoming.send(greeting).is_err() {
+      return Ok(());
+    }
+  }
+}
+
+// Connects to `url` again as `role`, backing off between attempts, and returns the room's first
+// message along with the stream. None when the transfer asked to close in the meantime.
+async fn reconnect(
+  url: &Url,
+  role: &str,
+  close_rx: &mut oneshot::Receiver<()>,
+) -> Result<Option<(SignalingStream, String)>> {
+  let mut delay = RECONNECT_DELAY;
+  let mut last_error = String::new();
+  for attempt in 1..=RECONNECT_ATTEMPTS {
+    log_line(
+      "[ws] reconnecting",
+      &format!("in {}s (attempt {attempt}/{RECONNECT_ATTEMPTS})", delay.as_secs()),
+    );
+    events::emit(Event::Reconnecting {
+      attempt,
+      delay: delay.as_secs(),
+    });
+    tokio::select! {
+      _ = tokio::time::sleep(delay) => {}
+      _ = &mut *close_rx => return Ok(None),
+    }
+    let attempt = match connect_async(url.as_str()).await {
+      Ok((mut stream, _)) => match greeting(&mut stream, role).await {
+        Ok(text) => {
+          log_line("[ws] reconnected", url.as_str());
+          events::emit(Event::Reconnected);
+          return Ok(Some((stream, text)));
+        }
+        Err(err) => {
+          let _ = stream.close(None).await;
+          err
+        }
+      },
+      Err(err) => err.to_string(),
+    };
+    log_line("[ws] reconnect failed", &attempt);
+    last_error = attempt;
+    delay = (delay * 2).min(RECONNECT_DELAY_MAX);
+  }
+  Err(ErrorKind::Signaling.error(format!(
+    "signaling server unreachable after {RECONNECT_ATTEMPTS} attempts to reconnect: {last_error}"
+  )))
+}
+
+// The room's first message, which says what role it gave us. Anything but `role` means someone else
+// holds ours for now: another sender, or, for a receiver, nobody yet.
+async fn greeting(stream: &mut SignalingStream, role: &str) -> std::result::Result<String, String> {
+  while let Some(msg) = stream.next().await {
+    match msg {
+      Ok(Message::Text(text)) => {
+        return match serde_json::from_str::<ServerMessage>(&text) {
+          Ok(ServerMessage::Role { role: given, .. }) if given != role => Err(format!("the room made us the {given}")),
+          _ => Ok(text),
+        };
+      }
+      Ok(_) => {}
+      Err(err) => return Err(err.to_string()),
+    }
+  }
+  Err("connection lost".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use tokio::net::TcpListener;
+  use tokio::time::Instant;
+
+  // A room that greets each connection with the next of `roles` and keeps it open.
+  async fn room(roles: &'static [&'static str]) -> Url {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = Url::parse(&format!("ws://{}/ws/ROOM123456", listener.local_addr().unwrap())).unwrap();
+    tokio::spawn(async move {
+      let mut open = Vec::new();
+      for role in roles {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+        let role = serde_json::json!({ "type": "role", "role": role, "cid": "c" }).to_string();
+        let _ = ws.send(Message::Text(role)).await;
+        open.push(ws);
+      }
+      std::future::pending::<()>().await;
+    });
+    url
+  }
+
+  #[tokio::test(start_paused = true)]
+  async fn reconnect_backs_off_then_gives_up() {
+    // Nothing listens on a port that was just given back.
+    let port = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap().port();
+    let url = Url::parse(&format!("ws://127.0.0.1:{port}/ws/ROOM123456")).unwrap();
+    let (_close, mut close_rx) = oneshot::channel();
+    let started = Instant::now();
+    let err = reconnect(&url, "offerer", &mut close_rx).await.unwrap_err();
+    // 1 + 2 + 4 + 8 + 16, then 30 for each of the last three.
+    assert_eq!(started.elapsed(), Duration::from_secs(121));
+    assert_eq!(crate::error::kind_of(&err), Some(ErrorKind::Signaling));
+    assert!(err.to_string().contains("after 8 attempts"), "{err}");
+  }
+
+  #[tokio::test(start_paused = true)]
+  async fn reconnect_retries_until_the_room_gives_the_role_back() {
+    let url = room(&["answerer", "offerer"]).await;
+    let (_close, mut close_rx) = oneshot::channel();
+    let started = Instant::now();
+    let (_stream, greeting) = reconnect(&url, "offerer", &mut close_rx).await.unwrap().unwrap();
+    assert_eq!(started.elapsed(), Duration::from_secs(3));
+    assert!(matches!(serde_json::from_str(&greeting), Ok(ServerMessage::Role { role, .. }) if role == "offerer"));
+  }
+
+  #[tokio::test(start_paused = true)]
+  async fn reconnect_stops_waiting_once_closed() {
+    let url = room(&[]).await;
+    let (close, mut close_rx) = oneshot::channel();
+    close.send(()).unwrap();
+    assert!(reconnect(&url, "offerer", &mut close_rx).await.unwrap().is_none());
+  }
+}
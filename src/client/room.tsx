@@ -15,6 +15,7 @@ type RoomMessage =
   | { type: "wait"; position?: number }
   | { type: "start"; peerId?: string }
   | { type: "peer-left"; peerId: string }
+  | { type: "closing"; reason: string }
   | { type: "offer"; from: string; sid: number; sdp: RTCSessionDescriptionInit }
   | { type: "answer"; from: string; sid: number; sdp: RTCSessionDescriptionInit }
   | { type: "candidate"; from: string; sid: number; candidate: RTCIceCandidateInit };
@@ -36,7 +37,15 @@ type IncomingMeta = {
 
 type DoneMessage = { type: "done" };
 
-type DataMessage = IncomingMeta | DoneMessage;
+/** Another data message encrypted with the room key (sent by the CLI when encryption is on). */
+type SealedMessage = { type: "sealed"; data: string };
+
+/** Key-possession check sent by the CLI before any file data; answered with a `proof`. */
+type ChallengeMessage = { type: "challenge"; nonce: string };
+
+type RejectedMessage = { type: "rejected"; reason: string };
+
+type DataMessage = IncomingMeta | DoneMessage | ChallengeMessage | RejectedMessage;
 
 type OutgoingMeta = IncomingMeta;
 
@@ -102,6 +111,7 @@ function RoomApp({ roomId, maxConcurrent }: RoomAppProps) {
   const sendIntentRef = useRef(false);
   const wsRef = useRef<AnyWebSocket | null>(null);
   const cryptoKeyRef = useRef<RoomCryptoKey>(null);
+  const authKeyRef = useRef<RoomCryptoKey>(null);
 
   const offererPeersRef = useRef<Map<string, OffererPeer>>(new Map());
 
@@ -304,6 +314,7 @@ function RoomApp({ roomId, maxConcurrent }: RoomAppProps) {
 
       const keyParam = new URLSearchParams(location.hash.slice(1)).get("k");
       cryptoKeyRef.current = keyParam ? await importAesKey(b64urlDecode(keyParam)) : null;
+      authKeyRef.current = keyParam ? await importHmacKey(b64urlDecode(keyParam)) : null;
 
       const ws = await connectSignaling(roomId, clientId);
       wsRef.current = ws;
@@ -424,8 +435,31 @@ function RoomApp({ roomId, maxConcurrent }: RoomAppProps) {
 
         ch.onmessage = async (ev) => {
           if (typeof ev.data === "string") {
-            const m = safeJson(ev.data) as DataMessage | null;
-            if (!m) return;
+            let m = safeJson(ev.data) as DataMessage | SealedMessage | null;
+            if (m?.type === "sealed") {
+              if (!cryptoKeyRef.current) {
+                setStatus(t.status.missingKey);
+                return;
+              }
+              m = await openSealed(m.data, cryptoKeyRef.current);
+            }
+            if (!m || m.type === "sealed") return;
+
+            if (m.type === "challenge") {
+              if (!authKeyRef.current) {
+                setStatus(t.status.missingKey);
+                return;
+              }
+              const mac = await keyProof(authKeyRef.current, roomId, b64urlDecode(m.nonce));
+              ch.send(JSON.stringify({ type: "proof", mac }));
+              return;
+            }
+
+            if (m.type === "rejected") {
+              log("[recv] rejected:", m.reason);
+              setStatus(t.status.keyRejected);
+              return;
+            }
 
             if (m.type === "meta") {
               log("[recv] starting:", m.name, "size:", m.size);
@@ -548,6 +582,13 @@ function RoomApp({ roomId, maxConcurrent }: RoomAppProps) {
           return;
         }
 
+        if (msg.type === "closing") {
+          if (roleRef.current === "answerer") {
+            setStatus(t.status.senderClosing);
+          }
+          return;
+        }
+
         if (msg.type === "start") {
           if (roleRef.current === "offerer" && msg.peerId) {
             if (offererPeersRef.current.has(msg.peerId)) {
@@ -1025,6 +1066,17 @@ async function toArrayBuffer(data: BufferLike) {
 }
 
 /** ---------- crypto (optional E2E) ---------- */
+/** HKDF-SHA256 subkey of the room key, so the key proof never uses the AES-GCM key (matches the CLI). */
+async function deriveSubkey(raw: Uint8Array, info: string) {
+  const base = await crypto.subtle.importKey("raw", raw, "HKDF", false, ["deriveBits"]);
+  const bits = await crypto.subtle.deriveBits(
+    { name: "HKDF", hash: "SHA-256", salt: new Uint8Array(0), info: new TextEncoder().encode(info) },
+    base,
+    256
+  );
+  return new Uint8Array(bits);
+}
+
 async function importAesKey(raw: Uint8Array) {
   return crypto.subtle.importKey("raw", raw, { name: "AES-GCM" }, false, [
     "encrypt",
@@ -1032,6 +1084,21 @@ async function importAesKey(raw: Uint8Array) {
   ]);
 }
 
+async function importHmacKey(raw: Uint8Array) {
+  const key = await deriveSubkey(raw, "pairlane-auth-v1");
+  return crypto.subtle.importKey("raw", key, { name: "HMAC", hash: "SHA-256" }, false, ["sign"]);
+}
+
+/** HMAC-SHA256 with the auth subkey over "pairlane-auth-v1\0" + roomId + "\0" + nonce, matching the CLI. */
+async function keyProof(key: CryptoKey, roomId: string, nonce: Uint8Array) {
+  const label = new TextEncoder().encode(`pairlane-auth-v1\0${roomId}\0`);
+  const input = new Uint8Array(label.length + nonce.length);
+  input.set(label, 0);
+  input.set(nonce, label.length);
+  const mac = await crypto.subtle.sign("HMAC", key, input);
+  return b64urlEncode(new Uint8Array(mac));
+}
+
 async function encryptChunk(plainAb: ArrayBuffer, key: RoomCryptoKey) {
   if (!key) return plainAb;
   const iv = crypto.getRandomValues(new Uint8Array(12));
@@ -1051,6 +1118,21 @@ async function decryptChunk(frameAb: ArrayBuffer, key: RoomCryptoKey) {
   return pt;
 }
 
+async function openSealed(data: string, key: RoomCryptoKey) {
+  try {
+    const plain = await decryptChunk(b64urlDecode(data).buffer, key);
+    return safeJson(new TextDecoder().decode(plain)) as DataMessage | SealedMessage | null;
+  } catch {
+    return null;
+  }
+}
+
+function b64urlEncode(bytes: Uint8Array) {
+  let bin = "";
+  for (let i = 0; i < bytes.length; i++) bin += String.fromCharCode(bytes[i]);
+  return btoa(bin).replace(/\+/g, "-").replace(/\//g, "_").replace(/=+$/, "");
+}
+
 function b64urlDecode(s: string) {
   const pad = "=".repeat((4 - (s.length % 4)) % 4);
   const b64 = (s + pad).replace(/-/g, "+").replace(/_/g, "/");
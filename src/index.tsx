@@ -99,6 +99,22 @@ app.post("/api/rooms", async (c) => {
   return c.json({ roomId });
 });
 
+// Settings of a room, so a link can be checked before it is shared. Who is connected is not shown,
+// and the creator's client ID stays private: whoever presents it becomes the room's sender.
+app.get("/api/rooms/:roomId", async (c) => {
+  const roomId = c.req.param("roomId");
+  const id = c.env.ROOM.idFromName(roomId);
+  const stub = c.env.ROOM.get(id);
+  const config = (await stub.fetch("https://room/config").then((res) => res.json())) as {
+    maxConcurrent?: number;
+    created?: boolean;
+  };
+  if (!config.created) {
+    return c.text("Room not found", 404);
+  }
+  return c.json({ roomId, maxConcurrent: normalizeMaxConcurrent(config.maxConcurrent) });
+});
+
 app.get("/ws/:roomId", (c) => {
   const upgrade = c.req.header("Upgrade");
   if (!upgrade || upgrade.toLowerCase() !== "websocket") {
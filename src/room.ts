@@ -1,4 +1,5 @@
 // Design: see README.md for the P2P signaling flow; this Durable Object pairs with src/index.tsx.
+// cli/src/server.rs mirrors this logic for `pairlane server`; keep the two in step.
 
 type Role = "offerer" | "answerer";
 
@@ -10,6 +11,7 @@ type ServerToClient =
   | { type: "wait"; position?: number }
   | { type: "start"; peerId?: string }
   | { type: "peer-left"; peerId: string }
+  | { type: "closing"; reason: string }
   | { type: "offer"; from: string; sid: number; sdp: RTCSessionDescriptionInit }
   | { type: "answer"; from: string; sid: number; sdp: RTCSessionDescriptionInit }
   | { type: "candidate"; from: string; sid: number; candidate: RTCIceCandidateInit };
@@ -18,13 +20,17 @@ type ClientToServer =
   | { type: "offer"; to: string; sid: number; sdp: RTCSessionDescriptionInit }
   | { type: "answer"; to: string; sid: number; sdp: RTCSessionDescriptionInit }
   | { type: "candidate"; to: string; sid: number; candidate: RTCIceCandidateInit }
-  | { type: "transfer-done"; peerId: string };
+  | { type: "transfer-done"; peerId: string }
+  | { type: "peer-rejected"; peerId: string; reason?: string }
+  | { type: "closing"; reason: string; peerId?: string };
 
 type SocketAttachment = {
   cid: string;
   role: Role;
   state?: AnswererState;
   joinedAt?: number;
+  // Offerer only: set once the sender takes no more receivers.
+  closing?: string;
 };
 
 type RoomConfig = {
@@ -85,8 +91,10 @@ export class Room extends DurableObjectBase {
         return new Response("OK");
       }
       if (request.method === "GET") {
-        await this.ensureConfig();
-        return Response.json(this.config ?? { maxConcurrent: DEFAULT_MAX_CONCURRENT });
+        // A room exists once it was created through the API or someone connected to it.
+        const created = (await this.ctx.storage.get("config")) !== undefined || this.openSockets().length > 0;
+        const config = await this.ensureConfig();
+        return Response.json({ ...config, created });
       }
       return new Response("Expected POST or GET", { status: 400 });
     }
@@ -117,14 +125,16 @@ export class Room extends DurableObjectBase {
       role,
       joinedAt: Date.now(),
     };
-    if (role === "answerer") attachment.state = "waiting";
+    // An answerer arriving after the sender stopped taking receivers is told so instead of queued.
+    const closing = role === "answerer" ? this.offererClosing() : undefined;
+    if (role === "answerer") attachment.state = closing === undefined ? "waiting" : "done";
 
     this.ctx.acceptWebSocket(server);
     server.serializeAttachment(attachment);
 
     this.sendJson(server, { type: "role", role, cid: clientId });
     if (role === "answerer") {
-      this.sendJson(server, { type: "wait" });
+      this.sendJson(server, closing === undefined ? { type: "wait" } : { type: "closing", reason: closing });
     }
 
     this.broadcastPeers();
@@ -145,7 +155,8 @@ export class Room extends DurableObjectBase {
       return;
     }
 
-    if (msg.type === "transfer-done") {
+    // A rejected peer (failed key check) frees its slot the same way a finished one does.
+    if (msg.type === "transfer-done" || msg.type === "peer-rejected") {
       if (attachment.role !== "offerer") return;
       const peerSocket = this.socketByCid(msg.peerId);
       if (peerSocket) {
@@ -155,6 +166,21 @@ export class Room extends DurableObjectBase {
       return;
     }
 
+    // The sender takes no more receivers: everyone still queued (and `peerId`, a receiver it was
+    // started with but turns away) is told why and never started.
+    if (msg.type === "closing") {
+      if (attachment.role !== "offerer") return;
+      attachment.closing = msg.reason;
+      ws.serializeAttachment(attachment);
+      for (const socket of this.answererSockets()) {
+        const peer = socket.deserializeAttachment() as SocketAttachment | null;
+        if (peer?.state !== "waiting" && peer?.cid !== msg.peerId) continue;
+        this.setAnswererState(socket, "done");
+        this.sendJson(socket, { type: "closing", reason: msg.reason });
+      }
+      return;
+    }
+
     if (msg.type === "offer" || msg.type === "answer" || msg.type === "candidate") {
       if (msg.type === "offer") {
         if (attachment.role !== "offerer") return;
@@ -265,6 +291,11 @@ export class Room extends DurableObjectBase {
     return null;
   }
 
+  private offererClosing() {
+    const offerer = this.getOffererSocket();
+    return (offerer?.deserializeAttachment() as SocketAttachment | null)?.closing;
+  }
+
   private answererSockets() {
     const out: WebSocket[] = [];
     for (const socket of this.openSockets()) {